@@ -1,11 +1,227 @@
+use super::diagnostic::Span;
+use std::fmt;
 use std::string::String;
 
+/// A node of the syntax tree and the source it was parsed from
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+impl Node {
+    pub fn new(kind: NodeKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Direct sub-nodes, in source order
+    pub fn children(&self) -> Vec<&Node> {
+        match &self.kind {
+            NodeKind::IntExpr(_)
+            | NodeKind::NumberExpr(_)
+            | NodeKind::IdentExpr(_)
+            | NodeKind::BoolExpr(_)
+            | NodeKind::BreakExpr
+            | NodeKind::ContinueExpr => vec![],
+            NodeKind::BlockExpr { nodes, .. } | NodeKind::ArrayExpr(nodes) => {
+                nodes.iter().collect()
+            }
+            NodeKind::UnaryExpr { child, .. } | NodeKind::CastExpr { expr: child, .. } => {
+                vec![child.as_ref()]
+            }
+            NodeKind::BinaryExpr { lhs, rhs, .. } => vec![lhs.as_ref(), rhs.as_ref()],
+            NodeKind::InitExpr { ident, expr, .. }
+            | NodeKind::GlobalInitExpr { ident, expr, .. }
+            | NodeKind::AssignExpr { ident, expr } => vec![ident.as_ref(), expr.as_ref()],
+            NodeKind::ArrayInitExpr { ident, .. }
+            | NodeKind::GlobalArrayInitExpr { ident, .. }
+            | NodeKind::ConstExpr { ident, .. } => vec![ident.as_ref()],
+            NodeKind::IndexExpr { ident, index } => vec![ident.as_ref(), index.as_ref()],
+            NodeKind::FuncExpr { ident, body, .. } => vec![ident.as_ref(), body.as_ref()],
+            NodeKind::CallExpr { ident, args } => {
+                let mut children = vec![ident.as_ref()];
+                children.extend(args.iter());
+                children
+            }
+            NodeKind::CondExpr { cond, cons, alter } => {
+                let mut children = vec![cond.as_ref(), cons.as_ref()];
+                children.extend(alter.iter().map(|alter| alter.as_ref()));
+                children
+            }
+            NodeKind::CaseExpr {
+                expr,
+                arms,
+                default,
+            } => {
+                let mut children = vec![expr.as_ref()];
+                children.extend(arms.iter().map(|(_, body)| body));
+                children.extend(default.iter().map(|default| default.as_ref()));
+                children
+            }
+            NodeKind::WhileExpr { cond, body } => vec![cond.as_ref(), body.as_ref()],
+            NodeKind::ForExpr {
+                ident,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                let mut children = vec![ident.as_ref(), start.as_ref(), end.as_ref()];
+                children.extend(step.iter().map(|step| step.as_ref()));
+                children.push(body.as_ref());
+                children
+            }
+            NodeKind::ReturnExpr { ret } => vec![ret.as_ref()],
+        }
+    }
+
+    /// Whether the parameter `name` is used as a pointer (`*name`) in the node
+    pub fn dereferences(&self, name: &str) -> bool {
+        match &self.kind {
+            NodeKind::UnaryExpr {
+                op: UnaryOp::Deref,
+                child,
+            } if child.is_ident(name) => true,
+            _ => self
+                .children()
+                .into_iter()
+                .any(|child| child.dereferences(name)),
+        }
+    }
+
+    /// Whether the parameter `name` is used as an array (`name[i]`) in the node
+    pub fn indexes(&self, name: &str) -> bool {
+        match &self.kind {
+            NodeKind::IndexExpr { ident, .. } if ident.is_ident(name) => true,
+            NodeKind::CallExpr { ident, args }
+                if ident.is_builtin_len() && args.iter().any(|arg| arg.is_ident(name)) =>
+            {
+                true
+            }
+            _ => self.children().into_iter().any(|child| child.indexes(name)),
+        }
+    }
+
+    /// Whether every path through the node ends with a `return`
+    pub fn always_returns(&self) -> bool {
+        match &self.kind {
+            NodeKind::ReturnExpr { .. } => true,
+            NodeKind::BlockExpr { nodes, .. } => nodes.iter().any(Node::always_returns),
+            NodeKind::CondExpr {
+                cons,
+                alter: Some(alter),
+                ..
+            } => cons.always_returns() && alter.always_returns(),
+            NodeKind::CaseExpr {
+                arms,
+                default: Some(default),
+                ..
+            } => arms.iter().all(|(_, body)| body.always_returns()) && default.always_returns(),
+            _ => false,
+        }
+    }
+
+    /// Whether every path through the node jumps away, by `return`, `break` or `continue`
+    pub fn diverges(&self) -> bool {
+        match &self.kind {
+            NodeKind::ReturnExpr { .. } | NodeKind::BreakExpr | NodeKind::ContinueExpr => true,
+            NodeKind::BlockExpr { nodes, .. } => nodes.iter().any(Node::diverges),
+            NodeKind::CondExpr {
+                cons,
+                alter: Some(alter),
+                ..
+            } => cons.diverges() && alter.diverges(),
+            NodeKind::CaseExpr {
+                arms,
+                default: Some(default),
+                ..
+            } => arms.iter().all(|(_, body)| body.diverges()) && default.diverges(),
+            _ => false,
+        }
+    }
+
+    /// Whether every path through the node either gives a value or jumps away
+    ///
+    /// Declarations, expression statements and loops have no value, nor do
+    /// conditionals missing a branch
+    pub fn has_value(&self) -> bool {
+        match &self.kind {
+            NodeKind::BlockExpr { nodes, tail } => {
+                nodes.iter().any(Node::diverges)
+                    || matches!(Node::block_value(nodes, *tail), Some(last) if last.has_value())
+            }
+            NodeKind::CondExpr {
+                cons,
+                alter: Some(alter),
+                ..
+            } => cons.has_value() && alter.has_value(),
+            NodeKind::CaseExpr {
+                arms,
+                default: Some(default),
+                ..
+            } => arms.iter().all(|(_, body)| body.has_value()) && default.has_value(),
+            NodeKind::CondExpr { .. }
+            | NodeKind::CaseExpr { .. }
+            | NodeKind::InitExpr { .. }
+            | NodeKind::GlobalInitExpr { .. }
+            | NodeKind::ArrayInitExpr { .. }
+            | NodeKind::GlobalArrayInitExpr { .. }
+            | NodeKind::ConstExpr { .. }
+            | NodeKind::FuncExpr { .. }
+            | NodeKind::WhileExpr { .. }
+            | NodeKind::ForExpr { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// Node giving the value of a block, the last one unless it is an expression
+    /// statement (`x = e;` or `f();`)
+    pub fn block_value(nodes: &[Node], tail: bool) -> Option<&Node> {
+        nodes.last().filter(|last| {
+            tail || !matches!(
+                last.kind,
+                NodeKind::AssignExpr { .. } | NodeKind::CallExpr { .. }
+            )
+        })
+    }
+
+    /// `len(t)` gives the length of an array
+    pub fn is_builtin_len(&self) -> bool {
+        self.is_ident("len")
+    }
+
+    fn is_ident(&self, name: &str) -> bool {
+        match &self.kind {
+            NodeKind::IdentExpr(ident) => ident == name,
+            _ => false,
+        }
+    }
+}
+
+// Spans are left out so that trees can be compared by their shape only
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<NodeKind> for Node {
+    fn from(kind: NodeKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum Node {
+pub enum NodeKind {
+    IntExpr(i64),
     NumberExpr(f64),
     IdentExpr(String),
     BoolExpr(bool),
-    BlockExpr(Vec<Node>),
+    /// `tail` when the block ends with an expression, without `;`
+    BlockExpr {
+        nodes: Vec<Node>,
+        tail: bool,
+    },
     UnaryExpr {
         op: UnaryOp,
         child: Box<Node>,
@@ -15,21 +231,47 @@ pub enum Node {
         lhs: Box<Node>,
         rhs: Box<Node>,
     },
+    /// `ty` is the annotated type, of the elements for array literals
     InitExpr {
         ident: Box<Node>,
+        ty: Option<Type>,
         expr: Box<Node>,
     },
     GlobalInitExpr {
         ident: Box<Node>,
+        ty: Option<Type>,
         expr: Box<Node>,
     },
+    /// `ty` is the annotated type of the elements
+    ArrayInitExpr {
+        ident: Box<Node>,
+        size: u32,
+        ty: Option<Type>,
+    },
+    GlobalArrayInitExpr {
+        ident: Box<Node>,
+        size: u32,
+        ty: Option<Type>,
+    },
+    IndexExpr {
+        ident: Box<Node>,
+        index: Box<Node>,
+    },
+    ArrayExpr(Vec<Node>),
+    /// Uses of `ident` are replaced by `value` while parsing
+    ConstExpr {
+        ident: Box<Node>,
+        value: Constant,
+    },
     AssignExpr {
         ident: Box<Node>,
         expr: Box<Node>,
     },
+    /// Functions return floats unless annotated
     FuncExpr {
         ident: Box<Node>,
-        args: Vec<String>,
+        args: Vec<Param>,
+        ret: Option<Type>,
         body: Box<Node>,
     },
     CallExpr {
@@ -41,13 +283,131 @@ pub enum Node {
         cons: Box<Node>,
         alter: Option<Box<Node>>,
     },
+    /// Arms are tried on the integer part of `expr`
+    CaseExpr {
+        expr: Box<Node>,
+        arms: Vec<(i64, Node)>,
+        default: Option<Box<Node>>,
+    },
     WhileExpr {
         cond: Box<Node>,
         body: Box<Node>,
     },
+    /// `for ident in start..end step step`, `end` is excluded and `step` defaults to 1
+    ForExpr {
+        ident: Box<Node>,
+        start: Box<Node>,
+        end: Box<Node>,
+        step: Option<Box<Node>>,
+        body: Box<Node>,
+    },
     ReturnExpr {
         ret: Box<Node>,
     },
+    /// Leaves the innermost loop
+    BreakExpr,
+    /// Goes to the next iteration of the innermost loop
+    ContinueExpr,
+    /// Conversion inserted by the type checker
+    CastExpr {
+        ty: Type,
+        expr: Box<Node>,
+    },
+}
+
+/// Types of Gengo values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Pointer(Box<Type>),
+    /// Arrays are only used through their elements or by reference
+    Array(Box<Type>),
+}
+
+impl Type {
+    /// Numbers and booleans convert implicitly to one another
+    pub fn is_scalar(&self) -> bool {
+        matches!(self, Type::Int | Type::Float | Type::Bool)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Pointer(ty) => write!(f, "pointer to {}", ty),
+            Type::Array(ty) => write!(f, "array of {}", ty),
+        }
+    }
+}
+
+/// A value known at compile time
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Constant {
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Constant::Int(nb) => nb as f64,
+            Constant::Float(nb) => nb,
+            Constant::Bool(b) => f64::from(u8::from(b)),
+        }
+    }
+
+    /// Value converted to `ty`, following the implicit conversions
+    pub fn cast(self, ty: &Type) -> Option<Constant> {
+        Some(match (self, ty) {
+            (Constant::Bool(b), Type::Int) => Constant::Int(i64::from(b)),
+            (Constant::Float(nb), Type::Int) => Constant::Int(nb as i64),
+            (value, Type::Int) => value,
+            (value, Type::Float) => Constant::Float(value.to_f64()),
+            (value, Type::Bool) => Constant::Bool(value.to_f64() != 0.0),
+            _ => return None,
+        })
+    }
+
+    /// Literal standing for the constant
+    pub fn literal(self) -> NodeKind {
+        match self {
+            Constant::Int(nb) => NodeKind::IntExpr(nb),
+            Constant::Float(nb) => NodeKind::NumberExpr(nb),
+            Constant::Bool(b) => NodeKind::BoolExpr(b),
+        }
+    }
+}
+
+/// A function parameter, `ref` ones are aliases of the caller's variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub by_ref: bool,
+    pub ty: Option<Type>,
+}
+
+impl Param {
+    /// Type of the parameter in `body`, for references the type they alias
+    ///
+    /// The annotation decides, `ref t: [int]` for arrays. Unannotated parameters
+    /// are floats, pointers to floats when dereferenced, and unannotated
+    /// references used as arrays alias arrays of floats
+    pub fn type_in(&self, body: &Node) -> Type {
+        match &self.ty {
+            Some(ty) => ty.clone(),
+            None if self.by_ref && body.indexes(&self.name) => Type::Array(Box::new(Type::Float)),
+            None if !self.by_ref && body.dereferences(&self.name) => {
+                Type::Pointer(Box::new(Type::Float))
+            }
+            None => Type::Float,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -72,4 +432,8 @@ pub enum BinaryOp {
 pub enum UnaryOp {
     Sub,
     Not,
+    Address,
+    Deref,
+    /// Postfix `n!`
+    Factorial,
 }
@@ -1,29 +1,233 @@
-use super::ast::{BinaryOp, Node, UnaryOp};
-use super::parser::parse;
-use std::collections::HashMap;
+use super::ast::{BinaryOp, Constant, Node, NodeKind, Type, UnaryOp};
+use super::diagnostic::{Diagnostic, Span};
+use super::parser::{fold, parse};
+use super::resolve::{resolve, source_name};
+use super::runtime;
+use super::typeck::{check, int_exponent};
+use std::collections::{HashMap, HashSet};
 use std::f64::NAN;
+use std::fmt;
 
 use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::execution_engine::JitFunction;
 use inkwell::module::Module;
-use inkwell::types::{BasicTypeEnum, FloatType};
-use inkwell::values::{BasicValue, BasicValueEnum, FloatValue, FunctionValue, PointerValue};
-use inkwell::AddressSpace;
-use inkwell::{FloatPredicate, OptimizationLevel};
+use inkwell::types::{AnyType, AnyTypeEnum, BasicType, BasicTypeEnum, FloatType};
+use inkwell::values::{
+    AnyValue, BasicValue, BasicValueEnum, FloatValue, FunctionValue, IntValue, PointerValue,
+};
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, OptimizationLevel};
 
 pub type JitFunc = unsafe extern "C" fn() -> f64;
 
+/// Largest constant exponent of `^` turned into multiplications
+const MAX_UNROLLED_POW: f64 = 16.0;
+
+/// Prefix of the LLVM globals holding variables, identifiers cannot contain a `.`
+/// so that they never clash with functions or the runtime
+const VARIABLE_PREFIX: &str = "var.";
+
+#[derive(Debug, Clone)]
+pub struct CodegenOptions {
+    /// Check array indexes against the declared length at runtime
+    pub bounds_check: bool,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        Self { bounds_check: true }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodegenError {
+    UndefinedVariable {
+        name: String,
+        span: Span,
+    },
+    UnknownFunction {
+        name: String,
+        span: Span,
+    },
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    AssignToUndeclared {
+        name: String,
+        span: Span,
+    },
+    FunctionRedefinition {
+        name: String,
+        span: Span,
+    },
+    /// A function named like the entry point, the runtime or a builtin
+    ReservedName {
+        name: String,
+        span: Span,
+    },
+    TypeMismatch {
+        expected: String,
+        found: String,
+        span: Span,
+    },
+    Unsupported {
+        what: String,
+        span: Span,
+    },
+    /// The LLVM verifier rejected the code generated for `function`, a compiler bug
+    InvalidIr {
+        /// `None` for the top-level code
+        function: Option<String>,
+        message: String,
+        /// The offending function
+        ir: String,
+        span: Span,
+    },
+    /// The program was rejected before reaching codegen (syntax errors...)
+    Rejected(Vec<Diagnostic>),
+    /// The execution engine could not be created or has no entry point
+    Jit(String),
+    /// The program stopped on a runtime error (out of bounds index...)
+    Runtime(String),
+}
+
+impl CodegenError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CodegenError::UndefinedVariable { span, .. }
+            | CodegenError::UnknownFunction { span, .. }
+            | CodegenError::ArityMismatch { span, .. }
+            | CodegenError::AssignToUndeclared { span, .. }
+            | CodegenError::FunctionRedefinition { span, .. }
+            | CodegenError::ReservedName { span, .. }
+            | CodegenError::TypeMismatch { span, .. }
+            | CodegenError::Unsupported { span, .. }
+            | CodegenError::InvalidIr { span, .. } => Some(*span),
+            CodegenError::Rejected(_) | CodegenError::Jit(_) | CodegenError::Runtime(_) => None,
+        }
+    }
+
+    pub fn into_diagnostics(self, file: &str) -> Vec<Diagnostic> {
+        match self {
+            CodegenError::Rejected(diagnostics) => diagnostics,
+            error => vec![Diagnostic::new(
+                file,
+                error.span().unwrap_or_default(),
+                &error.to_string(),
+            )],
+        }
+    }
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodegenError::UndefinedVariable { name, .. } => {
+                write!(f, "undefined variable `{}`", name)
+            }
+            CodegenError::UnknownFunction { name, .. } => {
+                write!(f, "unknown function `{}`", name)
+            }
+            CodegenError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "function `{}` takes {} argument(s) but {} were supplied",
+                name, expected, found
+            ),
+            CodegenError::AssignToUndeclared { name, .. } => {
+                write!(f, "cannot assign to undeclared variable `{}`", name)
+            }
+            CodegenError::FunctionRedefinition { name, .. } => {
+                write!(f, "function `{}` is already defined", name)
+            }
+            CodegenError::ReservedName { name, .. } => {
+                write!(f, "`{}` is reserved for the compiler", name)
+            }
+            CodegenError::TypeMismatch {
+                expected, found, ..
+            } => write!(
+                f,
+                "mismatched types: expected {}, found {}",
+                expected, found
+            ),
+            CodegenError::Unsupported { what, .. } => write!(f, "unsupported: {}", what),
+            CodegenError::InvalidIr {
+                function: Some(function),
+                message,
+                ..
+            } => write!(
+                f,
+                "internal compiler error: invalid IR for function `{}`: {}",
+                function, message
+            ),
+            CodegenError::InvalidIr {
+                function: None,
+                message,
+                ..
+            } => write!(
+                f,
+                "internal compiler error: invalid IR for the top-level code: {}",
+                message
+            ),
+            CodegenError::Rejected(diagnostics) => {
+                let diagnostics: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+                write!(f, "{}", diagnostics.join("\n"))
+            }
+            CodegenError::Jit(message) => write!(f, "could not run the program: {}", message),
+            CodegenError::Runtime(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Storage of a variable
+#[derive(Debug, Clone, Copy)]
+enum Variable<'ctx> {
+    Scalar(PointerValue<'ctx>),
+    /// Arrays received by reference only know their length at runtime
+    Array {
+        ptr: PointerValue<'ctx>,
+        len: IntValue<'ctx>,
+    },
+}
+
+/// How a function receives one of its arguments
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Passing {
+    Value,
+    /// Pointer to the caller's variable
+    Ref,
+    /// Pointer to the first element followed by the length
+    ArrayRef,
+}
+
 struct RecursiveBuilder<'a, 'ctx> {
     f64_type: FloatType<'ctx>,
     builder: &'a Builder<'ctx>,
     module: &'a Module<'ctx>,
     context: &'ctx Context,
+    options: &'a CodegenOptions,
 
     pub fn_stack: Vec<FunctionValue<'ctx>>,
-    pub var_stack: Vec<HashMap<String, PointerValue<'ctx>>>,
+    pub var_stack: Vec<HashMap<String, Variable<'ctx>>>,
     pub block_stack: Vec<BasicBlock<'ctx>>,
+    /// Where `continue` and `break` jump to, for each enclosing loop
+    loop_stack: Vec<(BasicBlock<'ctx>, BasicBlock<'ctx>)>,
+
+    signatures: HashMap<String, Vec<Passing>>,
+    /// Source names of the arrays for bounds errors, one global string each
+    array_names: HashMap<String, PointerValue<'ctx>>,
+    /// Where the functions are defined, for internal errors
+    spans: HashMap<String, Span>,
+    /// Variables used by functions, global when declared by the top-level code
+    captured: HashSet<String>,
 }
 
 impl<'a, 'ctx> RecursiveBuilder<'a, 'ctx> {
@@ -34,15 +238,22 @@ impl<'a, 'ctx> RecursiveBuilder<'a, 'ctx> {
         builder: &'a Builder<'ctx>,
         function: &'a FunctionValue<'ctx>,
         block_stack: BasicBlock<'ctx>,
+        options: &'a CodegenOptions,
     ) -> Self {
         Self {
             f64_type,
             builder,
             module,
             context,
+            options,
             fn_stack: vec![*function],
             var_stack: vec![HashMap::new()],
             block_stack: vec![block_stack],
+            loop_stack: vec![],
+            signatures: HashMap::new(),
+            array_names: HashMap::new(),
+            spans: HashMap::new(),
+            captured: HashSet::new(),
         }
     }
 
@@ -51,7 +262,7 @@ impl<'a, 'ctx> RecursiveBuilder<'a, 'ctx> {
         self.module.get_function(name)
     }
 
-    fn create_entry_block_alloca(&self, name: &str) -> PointerValue<'ctx> {
+    fn create_entry_block_alloca(&self, name: &str, ty: BasicTypeEnum<'ctx>) -> PointerValue<'ctx> {
         let builder = self.context.create_builder();
         let entry = self
             .fn_stack
@@ -65,577 +276,2189 @@ impl<'a, 'ctx> RecursiveBuilder<'a, 'ctx> {
             None => builder.position_at_end(entry),
         }
 
-        builder.build_alloca(self.context.f64_type(), name)
+        builder.build_alloca(ty, name)
     }
 
-    pub fn build(&mut self, node: &Node) -> Option<FloatValue<'ctx>> {
-        // Resposition the "Write-Head"
-        self.reposition();
-
-        // Add the nodes
-        match node {
-            Node::NumberExpr(nb) => Some(self.f64_type.const_float(*nb)),
-            Node::BoolExpr(b) => match b {
-                true => Some(self.f64_type.const_float(1.0)),
-                false => Some(self.f64_type.const_float(0.0)),
-            },
-            Node::IdentExpr(name) => {
-                if let Some(var) = self.var_stack.last().unwrap().get(name.as_str()) {
-                    return Some(
-                        self.builder
-                            .build_load(*var, name.as_str())
-                            .into_float_value(),
-                    );
-                };
-                if let Some(var) = self.module.get_global(name.as_str()) {
-                    let load = var.as_pointer_value();
-                    return Some(self.builder.build_load(load, "test").into_float_value());
-                };
-                unimplemented!("Could not find matching variable");
-            }
-
-            Node::UnaryExpr { op, child } => {
-                let child = self.build(child).unwrap();
-                match op {
-                    UnaryOp::Sub => Some(self.builder.build_float_sub(
-                        self.f64_type.const_float(0.0),
-                        child,
-                        "tmpsub",
-                    )),
-                    // Not a generalized not...
-                    UnaryOp::Not => Some(self.builder.build_float_sub(
-                        self.f64_type.const_float(1.0),
-                        child,
-                        "tmpnot",
-                    )),
-                }
-            }
-            Node::BinaryExpr { op, lhs, rhs } => {
-                let lhs = self.build(lhs).unwrap();
-                let rhs = self.build(rhs).unwrap();
-                match op {
-                    BinaryOp::Add => Some(self.builder.build_float_add(lhs, rhs, "tmpadd")),
-                    BinaryOp::Sub => Some(self.builder.build_float_sub(lhs, rhs, "tmpsub")),
-                    BinaryOp::Mul => Some(self.builder.build_float_mul(lhs, rhs, "tmpmul")),
-                    BinaryOp::Div => Some(self.builder.build_float_div(lhs, rhs, "tmpdiv")),
-                    BinaryOp::Pow => unimplemented!(),
-                    BinaryOp::Eq => Some({
-                        let cmp = self.builder.build_float_compare(
-                            FloatPredicate::UEQ,
-                            lhs,
-                            rhs,
-                            "tmpeq",
-                        );
-
-                        self.builder.build_unsigned_int_to_float(
-                            cmp,
-                            self.context.f64_type(),
-                            "tmpbool",
-                        )
-                    }),
-                    BinaryOp::Ne => Some({
-                        let cmp = self.builder.build_float_compare(
-                            FloatPredicate::UNE,
-                            lhs,
-                            rhs,
-                            "tmpne",
-                        );
-
-                        self.builder.build_unsigned_int_to_float(
-                            cmp,
-                            self.context.f64_type(),
-                            "tmpbool",
-                        )
-                    }),
-                    BinaryOp::Lt => Some({
-                        let cmp = self.builder.build_float_compare(
-                            FloatPredicate::ULT,
-                            lhs,
-                            rhs,
-                            "tmplt",
-                        );
-
-                        self.builder.build_unsigned_int_to_float(
-                            cmp,
-                            self.context.f64_type(),
-                            "tmpbool",
-                        )
-                    }),
-                    BinaryOp::Gt => Some({
-                        let cmp = self.builder.build_float_compare(
-                            FloatPredicate::UGT,
-                            lhs,
-                            rhs,
-                            "tmpgt",
-                        );
-
-                        self.builder.build_unsigned_int_to_float(
-                            cmp,
-                            self.context.f64_type(),
-                            "tmpbool",
-                        )
-                    }),
-                    BinaryOp::Ge => Some({
-                        let cmp = self.builder.build_float_compare(
-                            FloatPredicate::UGE,
-                            lhs,
-                            rhs,
-                            "tmpge",
-                        );
-
-                        self.builder
-                            .build_unsigned_int_to_float(cmp, self.f64_type, "tmpbool")
-                    }),
-                    BinaryOp::Le => Some({
-                        let cmp = self.builder.build_float_compare(
-                            FloatPredicate::ULE,
-                            lhs,
-                            rhs,
-                            "tmple",
-                        );
-
-                        self.builder
-                            .build_unsigned_int_to_float(cmp, self.f64_type, "tmpmodbool")
-                    }),
-                    BinaryOp::Modulo => {
-                        let div = self.builder.build_float_div(lhs, rhs, "tmpmoddiv");
-                        let cast = self.builder.build_float_to_signed_int(
-                            div,
-                            self.context.i64_type(),
-                            "tmpint",
-                        );
-                        let cast = self.builder.build_signed_int_to_float(
-                            cast,
-                            self.f64_type,
-                            "tmpmodtrunc",
-                        );
-                        let mul = self.builder.build_float_mul(rhs, cast, "tmpmodmul");
-                        Some(self.builder.build_float_sub(lhs, mul, "tmpmod"))
-                    }
-                    BinaryOp::And => unimplemented!(),
-                    BinaryOp::Or => unimplemented!(),
-                }
-            }
-            Node::InitExpr { ident, expr } => {
-                if let Node::IdentExpr(name) = ident.as_ref() {
-                    let expr = self.build(expr);
-                    let alloca = self.create_entry_block_alloca(name);
-
-                    self.builder.build_store(alloca, expr.unwrap());
-
-                    self.var_stack
-                        .last_mut()
-                        .unwrap()
-                        .insert(name.to_string(), alloca);
-                    None
-                } else {
-                    unimplemented!()
-                }
+    /// Adds the prototypes of the functions defined in `node` to the module,
+    /// so that they can be called before their definition
+    fn declare_functions(&mut self, node: &Node) -> Result<(), CodegenError> {
+        if let NodeKind::FuncExpr {
+            ident,
+            args,
+            ret,
+            body,
+        } = &node.kind
+        {
+            let name = self.ident_name(ident, "function")?;
+            // `len` would be shadowed by the builtin
+            if runtime::is_reserved(name) || ident.is_builtin_len() {
+                return Err(CodegenError::ReservedName {
+                    name: String::from(name),
+                    span: ident.span,
+                });
             }
-            Node::GlobalInitExpr { ident, expr } => {
-                if let Node::IdentExpr(name) = ident.as_ref() {
-                    let a = self
-                        .module
-                        .add_global(self.f64_type, Some(AddressSpace::Const), name);
-                    a.set_initializer(&self.build(expr)?);
-                    None
-                } else {
-                    unimplemented!()
-                }
+            if self.signatures.contains_key(name) {
+                return Err(CodegenError::FunctionRedefinition {
+                    name: String::from(name),
+                    span: ident.span,
+                });
             }
-            Node::AssignExpr { ident, expr } => {
-                if let Node::IdentExpr(name) = ident.as_ref() {
-                    let nval = self.build(expr).unwrap();
 
-                    if let Some(var) = self.var_stack.last().unwrap().get(name.as_str()) {
-                        self.builder.build_store(*var, nval);
-                        return Some(nval);
+            // References are arrays when indexed in the body
+            let types: Vec<Type> = args.iter().map(|arg| arg.type_in(body)).collect();
+            let passing: Vec<Passing> = args
+                .iter()
+                .zip(&types)
+                .map(|(arg, ty)| match (arg.by_ref, ty) {
+                    (false, _) => Passing::Value,
+                    (true, Type::Array(_)) => Passing::ArrayRef,
+                    (true, _) => Passing::Ref,
+                })
+                .collect();
+
+            let mut args_types: Vec<BasicTypeEnum> = Vec::with_capacity(args.len());
+            for (ty, passing) in types.iter().zip(&passing) {
+                match passing {
+                    Passing::Value => args_types.push(self.llvm_type(ty)),
+                    Passing::Ref => {
+                        args_types.push(self.llvm_type(ty).ptr_type(AddressSpace::Generic).into())
+                    }
+                    Passing::ArrayRef => {
+                        args_types.push(self.llvm_type(ty));
+                        args_types.push(self.context.i64_type().into());
                     }
-
-                    if let Some(var) = self.module.get_global(name.as_str()) {
-                        self.builder.build_store(var.as_pointer_value(), nval);
-                        return Some(nval);
-                    };
-
-                    unreachable!("Could not find var {:?}", name.as_str());
-                } else {
-                    unimplemented!()
                 }
             }
 
-            Node::CondExpr { cond, cons, alter } => {
-                let parent = *self.fn_stack.last().unwrap();
-                let zero_const = self.context.f64_type().const_float(0.0);
+            let ret = self.llvm_type(ret.as_ref().unwrap_or(&Type::Float));
+            let fn_type = ret.fn_type(args_types.as_slice(), false);
+            self.module.add_function(name, fn_type, None);
+            self.signatures.insert(String::from(name), passing);
+            self.spans.insert(String::from(name), node.span);
+        }
 
-                // create condition by comparing without 0.0 and returning an int
-                let cond = self.build(cond)?;
-                let cond = self.builder.build_float_compare(
-                    FloatPredicate::ONE,
-                    cond,
-                    zero_const,
-                    "ifcond",
-                );
+        for child in node.children() {
+            self.declare_functions(child)?;
+        }
+        Ok(())
+    }
 
-                // build branch
-                let then_bb = self.context.append_basic_block(parent, "then");
-                let else_bb = self.context.append_basic_block(parent, "else");
-                let cont_bb = self.context.append_basic_block(parent, "ifcont");
+    /// Whether the current block already ends with a `ret` or a branch
+    fn is_terminated(&self) -> bool {
+        self.builder
+            .get_insert_block()
+            .and_then(|block| block.get_terminator())
+            .is_some()
+    }
 
-                self.builder
-                    .build_conditional_branch(cond, then_bb, else_bb);
+    /// Storage of a new variable, zeroed globals for the top-level ones which
+    /// functions read
+    fn create_variable(&self, name: &str, ty: BasicTypeEnum<'ctx>) -> PointerValue<'ctx> {
+        if self.fn_stack.len() > 1 || !self.captured.contains(name) {
+            return self.create_entry_block_alloca(name, ty);
+        }
+        let global = self.module.add_global(ty, None, &global_name(name));
+        global.set_initializer(&zero(ty));
+        global.as_pointer_value()
+    }
 
-                // build then block
-                self.block_stack.pop();
-                self.block_stack.push(then_bb);
-                self.reposition();
+    /// Variable stored at `ptr`, arrays of fixed size know their length
+    fn variable(&self, ptr: PointerValue<'ctx>) -> Variable<'ctx> {
+        match ptr.get_type().get_element_type() {
+            AnyTypeEnum::ArrayType(array) => Variable::Array {
+                ptr,
+                len: self.context.i64_type().const_int(array.len() as u64, false),
+            },
+            _ => Variable::Scalar(ptr),
+        }
+    }
 
-                let mut then_val = self.f64_type.const_float(NAN);
-                if let Some(r) = self.build(cons) {
-                    then_val = r;
-                }
-                self.builder.build_unconditional_branch(cont_bb);
+    /// Storage of a variable, locals shadow the top-level ones which shadow globals
+    ///
+    /// Names are unique once resolved, so scopes within a function need no stack
+    fn lookup(&self, name: &str) -> Option<Variable<'ctx>> {
+        if let Some(var) = self.var_stack.last().unwrap().get(name) {
+            return Some(*var);
+        }
+        if let Some(var) = self.var_stack.first().unwrap().get(name) {
+            return Some(*var);
+        }
+        self.module
+            .get_global(&global_name(name))
+            .map(|var| self.variable(var.as_pointer_value()))
+    }
 
-                let then_bb = self.builder.get_insert_block().unwrap();
+    /// Storage of a scalar variable, arrays must be indexed
+    fn lookup_scalar(&self, ident: &Node) -> Result<Option<PointerValue<'ctx>>, CodegenError> {
+        let name = self.ident_name(ident, "variable")?;
+        match self.lookup(name) {
+            Some(Variable::Array { .. }) => Err(CodegenError::Unsupported {
+                what: format!("array `{}` used without an index", source_name(name)),
+                span: ident.span,
+            }),
+            Some(Variable::Scalar(ptr)) => Ok(Some(ptr)),
+            None => Ok(None),
+        }
+    }
 
-                // build else block
-                self.block_stack.pop();
-                self.block_stack.push(else_bb);
-                self.reposition();
-                // FIXME
-                let mut else_val = self.f64_type.const_float(NAN);
-                if let Some(node) = alter {
-                    if let Some(r) = self.build(node) {
-                        else_val = r;
-                    }
-                }
-                self.builder.build_unconditional_branch(cont_bb);
+    /// Storage & length of the array named by `ident`
+    fn lookup_array(
+        &self,
+        ident: &Node,
+    ) -> Result<(PointerValue<'ctx>, IntValue<'ctx>), CodegenError> {
+        let name = self.ident_name(ident, "array")?;
+        match self.lookup(name) {
+            Some(Variable::Array { ptr, len }) => Ok((ptr, len)),
+            Some(Variable::Scalar(_)) => Err(CodegenError::Unsupported {
+                what: format!("indexing scalar `{}`", source_name(name)),
+                span: ident.span,
+            }),
+            None => Err(CodegenError::UndefinedVariable {
+                name: String::from(source_name(name)),
+                span: ident.span,
+            }),
+        }
+    }
 
-                let else_bb = self.builder.get_insert_block().unwrap();
+    /// Pointer to the array element `ident[index]`
+    fn build_element_ptr(
+        &mut self,
+        ident: &Node,
+        index: &Node,
+    ) -> Result<PointerValue<'ctx>, CodegenError> {
+        let name = self.ident_name(ident, "indexing")?;
+        let (array, len) = self.lookup_array(ident)?;
+
+        let i64_type = self.context.i64_type();
+        let span = index.span;
+        let index = self.build_int(index)?;
+
+        if self.options.bounds_check {
+            self.build_bounds_check(name, index, len, span)?;
+        }
 
-                // emit merge block
-                self.block_stack.pop();
-                self.block_stack.push(cont_bb);
-                self.reposition();
+        // Arrays received by reference are pointers to their first element
+        Ok(unsafe {
+            match is_array(array) {
+                true => self.builder.build_in_bounds_gep(
+                    array,
+                    &[i64_type.const_zero(), index],
+                    "tmpelem",
+                ),
+                false => self.builder.build_in_bounds_gep(array, &[index], "tmpelem"),
+            }
+        })
+    }
 
-                let phi = self.builder.build_phi(self.context.f64_type(), "iftmp");
+    /// Pointer to the first element & length, to pass `ident` by reference
+    fn build_array_ref(
+        &mut self,
+        ident: &Node,
+    ) -> Result<(PointerValue<'ctx>, IntValue<'ctx>), CodegenError> {
+        let (array, len) = self.lookup_array(ident)?;
+        let ptr = match is_array(array) {
+            true => {
+                let zero = self.context.i64_type().const_zero();
+                unsafe {
+                    self.builder
+                        .build_in_bounds_gep(array, &[zero, zero], "tmparray")
+                }
+            }
+            false => array,
+        };
+        Ok((ptr, len))
+    }
 
-                phi.add_incoming(&[(&then_val, then_bb), (&else_val, else_bb)]);
+    /// Stops the program through the runtime when `index` is not in `0..len`
+    fn build_bounds_check(
+        &mut self,
+        name: &str,
+        index: IntValue<'ctx>,
+        len: IntValue<'ctx>,
+        span: Span,
+    ) -> Result<(), CodegenError> {
+        let parent = *self.fn_stack.last().unwrap();
+        let i64_type = self.context.i64_type();
+
+        // Negative indexes are huge once compared as unsigned
+        let in_bounds = self
+            .builder
+            .build_int_compare(IntPredicate::ULT, index, len, "inbounds");
+
+        let fail_bb = self.context.append_basic_block(parent, "outofbounds");
+        let cont_bb = self.context.append_basic_block(parent, "inbounds");
 
-                self.block_stack.pop();
-                self.block_stack.push(cont_bb);
+        self.builder
+            .build_conditional_branch(in_bounds, cont_bb, fail_bb);
+
+        self.builder.position_at_end(fail_bb);
+        let report = runtime::bounds_error(self.context, self.module);
+        let builder = self.builder;
+        let name = source_name(name);
+        let name = *self
+            .array_names
+            .entry(String::from(name))
+            .or_insert_with(|| {
+                builder
+                    .build_global_string_ptr(name, "arrayname")
+                    .as_pointer_value()
+            });
+        self.builder.build_call(
+            report,
+            &[
+                name.into(),
+                index.into(),
+                len.into(),
+                i64_type.const_int(span.line as u64, false).into(),
+            ],
+            "",
+        );
+        self.build_stop(span)?;
+
+        self.block_stack.pop();
+        self.block_stack.push(cont_bb);
+        self.reposition();
+        Ok(())
+    }
 
-                Some(phi.as_basic_value().into_float_value())
-            }
+    /// Stops the program through the runtime when dividing `lhs` by `rhs` is
+    /// undefined: by zero, or `i64::MIN` by -1
+    fn build_division_check(
+        &mut self,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        span: Span,
+    ) -> Result<(), CodegenError> {
+        let parent = *self.fn_stack.last().unwrap();
+        let ty = rhs.get_type();
+
+        let zero =
+            self.builder
+                .build_int_compare(IntPredicate::EQ, rhs, ty.const_zero(), "divzero");
+        let min = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            lhs,
+            ty.const_int(i64::MIN as u64, false),
+            "divmin",
+        );
+        let minus_one = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            rhs,
+            ty.const_int(-1i64 as u64, true),
+            "divminusone",
+        );
+        let overflow = self.builder.build_and(min, minus_one, "divoverflow");
+        let invalid = self.builder.build_or(zero, overflow, "divinvalid");
+
+        let fail_bb = self.context.append_basic_block(parent, "divisionerror");
+        let cont_bb = self.context.append_basic_block(parent, "division");
 
-            Node::BlockExpr(nodes) => {
-                let mut result: Option<FloatValue> = None;
-                for node in nodes {
-                    result = self.build(node);
-                }
-                result
-            }
+        self.builder
+            .build_conditional_branch(invalid, fail_bb, cont_bb);
+
+        self.builder.position_at_end(fail_bb);
+        let report = runtime::division_error(self.context, self.module);
+        let i64_type = self.context.i64_type();
+        self.builder.build_call(
+            report,
+            &[
+                rhs.into(),
+                i64_type.const_int(span.line as u64, false).into(),
+            ],
+            "",
+        );
+        self.build_stop(span)?;
+
+        self.block_stack.pop();
+        self.block_stack.push(cont_bb);
+        self.reposition();
+        Ok(())
+    }
 
-            Node::FuncExpr { ident, args, body } => {
-                if let Node::IdentExpr(name) = ident.as_ref() {
-                    self.builder.get_insert_block();
+    /// Leaves the current function after a runtime error, raising the failure flag
+    fn build_stop(&self, span: Span) -> Result<(), CodegenError> {
+        let failed = runtime::failed(self.context, self.module);
+        let raised = self.context.bool_type().const_int(1, false);
+        self.builder.build_store(failed, raised);
+        self.build_default_return(span)
+    }
 
-                    // Compiling the prototype
-                    let args_types = std::iter::repeat(self.f64_type)
-                        .take(args.len())
-                        .map(|f| f.into())
-                        .collect::<Vec<BasicTypeEnum>>();
+    /// Leaves the current function when the function just called stopped on a
+    /// runtime error, so that the program stops up to the entry point
+    fn build_failure_check(&mut self, span: Span) -> Result<(), CodegenError> {
+        let parent = *self.fn_stack.last().unwrap();
+        let failed = runtime::failed(self.context, self.module);
+        let failed = self.builder.build_load(failed, "failed").into_int_value();
 
-                    let args_types = args_types.as_slice();
-                    let fn_type = self.f64_type.fn_type(args_types, false);
+        let fail_bb = self.context.append_basic_block(parent, "callfailed");
+        let cont_bb = self.context.append_basic_block(parent, "callcont");
+        self.builder
+            .build_conditional_branch(failed, fail_bb, cont_bb);
 
-                    let function = self.module.add_function(name, fn_type, None);
+        self.builder.position_at_end(fail_bb);
+        self.build_default_return(span)?;
 
-                    for (i, arg) in function.get_param_iter().enumerate() {
-                        arg.into_float_value().set_name(args[i].as_str());
-                    }
+        self.block_stack.pop();
+        self.block_stack.push(cont_bb);
+        self.reposition();
+        Ok(())
+    }
 
-                    // Add function block
-                    let entry = self.context.append_basic_block(function, "entry");
+    /// Returns from the current function without a value: NAN for floats, like
+    /// missing branches, zero otherwise
+    fn build_default_return(&self, span: Span) -> Result<(), CodegenError> {
+        let function = *self.fn_stack.last().unwrap();
+        let value = match function.get_type().get_return_type() {
+            Some(BasicTypeEnum::FloatType(ty)) => ty.const_float(NAN).into(),
+            Some(ty) => zero(ty),
+            None => {
+                let name = function.get_name().to_string_lossy();
+                return Err(CodegenError::Unsupported {
+                    what: format!("function `{}` without a result", name),
+                    span,
+                });
+            }
+        };
+        self.builder.build_return(Some(&value));
+        Ok(())
+    }
 
-                    self.fn_stack.push(function);
-                    self.block_stack.push(entry);
-                    self.var_stack.push(HashMap::new());
-                    self.reposition();
+    /// Builds a node which must produce a value (initializers, arguments...)
+    fn build_expr(&mut self, node: &Node) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        match self.build(node)? {
+            Some(value) => Ok(value),
+            None => Err(CodegenError::Unsupported {
+                what: String::from("statement used as a value"),
+                span: node.span,
+            }),
+        }
+    }
 
-                    // Build variable map
-                    self.var_stack.reserve(args.len());
-                    for (i, arg) in function.get_param_iter().enumerate() {
-                        let arg_name = args[i].as_str();
-                        let alloca = self.create_entry_block_alloca(arg_name);
-                        self.builder.build_store(alloca, arg);
-                        self.var_stack
-                            .last_mut()
-                            .unwrap()
-                            .insert(args[i].clone(), alloca);
-                    }
+    /// Builds a node which must produce a number, an integer or a float
+    fn build_number(&mut self, node: &Node) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        match self.build_expr(node)? {
+            value @ BasicValueEnum::IntValue(_) | value @ BasicValueEnum::FloatValue(_) => {
+                Ok(value)
+            }
+            value => Err(mismatch("number", value, node.span)),
+        }
+    }
 
-                    // Compile Body
-                    self.build(body);
+    /// Builds a number converted to a float (mixed operands, pow...)
+    fn build_float(&mut self, node: &Node) -> Result<FloatValue<'ctx>, CodegenError> {
+        let value = self.build_number(node)?;
+        Ok(self.float(value))
+    }
 
-                    // FIXME : All functions must return...
-                    self.builder.build_return(None);
+    /// Builds a number truncated to an integer (indexes, case...)
+    fn build_int(&mut self, node: &Node) -> Result<IntValue<'ctx>, CodegenError> {
+        let value = self.build_number(node)?;
+        let i64_type = self.context.i64_type().as_any_type_enum();
+        Ok(self
+            .build_cast(value, i64_type, node.span)?
+            .into_int_value())
+    }
 
-                    self.fn_stack.pop();
-                    self.block_stack.pop();
-                    self.var_stack.pop();
+    /// Builds a condition, numbers are true when they are not zero
+    fn build_cond(&mut self, node: &Node, name: &str) -> Result<IntValue<'ctx>, CodegenError> {
+        Ok(match self.build_number(node)? {
+            BasicValueEnum::IntValue(value) if is_bool(value) => value,
+            BasicValueEnum::IntValue(value) => self.builder.build_int_compare(
+                IntPredicate::NE,
+                value,
+                value.get_type().const_zero(),
+                name,
+            ),
+            value => self.builder.build_float_compare(
+                FloatPredicate::ONE,
+                value.into_float_value(),
+                self.f64_type.const_zero(),
+                name,
+            ),
+        })
+    }
 
-                    self.reposition();
+    /// Converts a number to a float
+    fn float(&self, value: BasicValueEnum<'ctx>) -> FloatValue<'ctx> {
+        match value {
+            BasicValueEnum::IntValue(value) if is_bool(value) => self
+                .builder
+                .build_unsigned_int_to_float(value, self.f64_type, "tmpfloat"),
+            BasicValueEnum::IntValue(value) => {
+                self.builder
+                    .build_signed_int_to_float(value, self.f64_type, "tmpfloat")
+            }
+            value => value.into_float_value(),
+        }
+    }
 
-                    None
-                } else {
-                    unimplemented!()
-                }
+    /// Converts `value` to `ty` when both are scalars, other values must match
+    fn build_cast(
+        &self,
+        value: BasicValueEnum<'ctx>,
+        ty: AnyTypeEnum<'ctx>,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        Ok(match (value, ty) {
+            (value, ty) if value.get_type().as_any_type_enum() == ty => value,
+            (BasicValueEnum::IntValue(_), AnyTypeEnum::FloatType(_)) => self.float(value).into(),
+            (BasicValueEnum::IntValue(value), AnyTypeEnum::IntType(ty))
+                if ty.get_bit_width() == 1 =>
+            {
+                self.builder
+                    .build_int_compare(
+                        IntPredicate::NE,
+                        value,
+                        value.get_type().const_zero(),
+                        "tmpbool",
+                    )
+                    .into()
             }
+            // Only booleans are narrower than integers
+            (BasicValueEnum::IntValue(value), AnyTypeEnum::IntType(ty)) => {
+                self.builder.build_int_z_extend(value, ty, "tmpint").into()
+            }
+            (BasicValueEnum::FloatValue(value), AnyTypeEnum::IntType(ty))
+                if ty.get_bit_width() == 1 =>
+            {
+                self.builder
+                    .build_float_compare(
+                        FloatPredicate::ONE,
+                        value,
+                        self.f64_type.const_zero(),
+                        "tmpbool",
+                    )
+                    .into()
+            }
+            (BasicValueEnum::FloatValue(value), AnyTypeEnum::IntType(ty)) => self
+                .builder
+                .build_float_to_signed_int(value, ty, "tmpint")
+                .into(),
+            (value, ty) => return Err(mismatch(&type_name(ty), value, span)),
+        })
+    }
 
-            Node::ReturnExpr { ret } => {
-                let ret = self.build(ret)?;
-                self.builder.build_return(Some(&ret));
-                None
+    /// LLVM type of a Gengo type, arrays are pointers to their first element
+    fn llvm_type(&self, ty: &Type) -> BasicTypeEnum<'ctx> {
+        match ty {
+            Type::Int => self.context.i64_type().into(),
+            Type::Float => self.f64_type.into(),
+            Type::Bool => self.context.bool_type().into(),
+            Type::Pointer(ty) | Type::Array(ty) => {
+                self.llvm_type(ty).ptr_type(AddressSpace::Generic).into()
             }
+        }
+    }
 
-            Node::CallExpr { ident, args } => {
-                if let Node::IdentExpr(name) = ident.as_ref() {
-                    match self.get_function(name) {
-                        Some(fun) => {
-                            let mut compiled_args = Vec::with_capacity(args.len());
+    /// Builds a node which must produce a pointer
+    fn build_pointer(&mut self, node: &Node) -> Result<PointerValue<'ctx>, CodegenError> {
+        match self.build_expr(node)? {
+            BasicValueEnum::PointerValue(ptr) => Ok(ptr),
+            value => Err(mismatch("pointer", value, node.span)),
+        }
+    }
 
-                            for arg in args {
-                                compiled_args.push(self.build(arg)?);
-                            }
+    /// Storage designated by `node`, for `&node` and assignments
+    fn build_address(&mut self, node: &Node) -> Result<PointerValue<'ctx>, CodegenError> {
+        match &node.kind {
+            NodeKind::IdentExpr(name) => match self.lookup_scalar(node)? {
+                Some(var) => Ok(var),
+                None => Err(CodegenError::UndefinedVariable {
+                    name: String::from(source_name(name)),
+                    span: node.span,
+                }),
+            },
+            NodeKind::IndexExpr { ident, index } => self.build_element_ptr(ident, index),
+            NodeKind::UnaryExpr {
+                op: UnaryOp::Deref,
+                child,
+            } => self.build_pointer(child),
+            _ => Err(CodegenError::Unsupported {
+                what: String::from("expression without an address"),
+                span: node.span,
+            }),
+        }
+    }
 
-                            let argsv: Vec<BasicValueEnum> = compiled_args
-                                .iter()
-                                .by_ref()
-                                .map(|&val| val.into())
-                                .collect();
+    /// Ends the current branch by jumping to `cont`, unless it already returned
+    ///
+    /// Gives the block the branch ended in, which `cont` merges
+    fn build_branch_end(&mut self, cont: BasicBlock<'ctx>) -> Option<BasicBlock<'ctx>> {
+        if self.is_terminated() {
+            return None;
+        }
+        self.builder.build_unconditional_branch(cont);
+        self.builder.get_insert_block()
+    }
 
-                            if fun.count_params() != (argsv.len() as u32) {
-                                panic!("PLop");
-                            }
+    /// Value of a construct whose branches meet at the current block
+    ///
+    /// A missing number is NAN, other values must come from every branch.
+    /// Branches which returned are left out, when all did the block is unreachable.
+    fn build_merge(
+        &mut self,
+        incoming: &[(Option<BasicValueEnum<'ctx>>, Option<BasicBlock<'ctx>>)],
+        name: &str,
+    ) -> Option<BasicValueEnum<'ctx>> {
+        let incoming: Vec<(Option<BasicValueEnum>, BasicBlock)> = incoming
+            .iter()
+            .filter_map(|(value, bb)| bb.map(|bb| (*value, bb)))
+            .collect();
+        if incoming.is_empty() {
+            self.builder.build_unreachable();
+            return None;
+        }
 
-                            match self
-                                .builder
-                                .build_call(fun, argsv.as_slice(), "tmp")
-                                .try_as_basic_value()
-                                .left()
-                            {
-                                Some(value) => Some(value.into_float_value()),
-                                None => unreachable!("Invalid call produced."),
-                            }
-                        }
-                        None => unreachable!("Unknown function."),
-                    }
-                } else {
-                    unimplemented!();
+        let nan = self.f64_type.const_float(NAN).into();
+        let mut values: Vec<BasicValueEnum> = incoming
+            .iter()
+            .map(|(value, _)| value.unwrap_or(nan))
+            .collect();
+
+        // Values typeck's `merge` could not type are converted at the end of their branch
+        let numbers = values
+            .iter()
+            .all(|value| value.is_int_value() || value.is_float_value());
+        if numbers && values.iter().any(|value| value.is_float_value()) {
+            for (value, (_, bb)) in values.iter_mut().zip(&incoming) {
+                if value.is_int_value() {
+                    self.builder.position_before(&bb.get_terminator().unwrap());
+                    *value = self.float(*value).into();
                 }
             }
+            self.reposition();
+        }
 
-            Node::WhileExpr {
-                cond: condexpr,
-                body,
-            } => {
-                let parent = *self.fn_stack.last().unwrap();
-                let zero_const = self.context.f64_type().const_float(0.0);
+        let ty = values.first()?.get_type();
+        if values.iter().any(|value| value.get_type() != ty) {
+            return None;
+        }
 
-                // build branch
-                let loop_entry = self.context.append_basic_block(parent, "loop");
-                let loop_exit = self.context.append_basic_block(parent, "exitloop");
+        let phi = self.builder.build_phi(ty, name);
+        let incoming: Vec<(&dyn BasicValue<'ctx>, BasicBlock<'ctx>)> = values
+            .iter()
+            .zip(&incoming)
+            .map(|(value, (_, bb))| (value as &dyn BasicValue<'ctx>, *bb))
+            .collect();
+        phi.add_incoming(&incoming);
 
-                // Loop condition
-                let cond = self.build(condexpr)?;
-                let cond = self.builder.build_float_compare(
-                    FloatPredicate::ONE,
-                    cond,
-                    zero_const,
-                    "loopcond",
-                );
+        Some(phi.as_basic_value())
+    }
 
-                self.builder
-                    .build_conditional_branch(cond, loop_entry, loop_exit);
+    /// `&&` and `||`, the right operand is only evaluated when needed
+    fn build_logical(
+        &mut self,
+        op: BinaryOp,
+        lhs: &Node,
+        rhs: &Node,
+    ) -> Result<IntValue<'ctx>, CodegenError> {
+        let parent = *self.fn_stack.last().unwrap();
+
+        let lhs = self.build_cond(lhs, "lhscond")?;
+        let lhs_bb = self.builder.get_insert_block().unwrap();
+
+        let rhs_bb = self.context.append_basic_block(parent, "logicrhs");
+        let cont_bb = self.context.append_basic_block(parent, "logiccont");
+
+        // The result is known when `false && _` or `true || _`
+        let known = match op {
+            BinaryOp::And => {
+                self.builder.build_conditional_branch(lhs, rhs_bb, cont_bb);
+                self.context.bool_type().const_int(0, false)
+            }
+            _ => {
+                self.builder.build_conditional_branch(lhs, cont_bb, rhs_bb);
+                self.context.bool_type().const_int(1, false)
+            }
+        };
 
-                self.block_stack.pop();
-                self.block_stack.push(loop_entry);
-                self.reposition();
+        self.block_stack.pop();
+        self.block_stack.push(rhs_bb);
+        self.reposition();
 
-                self.build(body);
+        let rhs = self.build_cond(rhs, "rhscond")?;
+        self.builder.build_unconditional_branch(cont_bb);
 
-                // Reloop
-                let cond = self.build(condexpr)?;
-                let cond = self.builder.build_float_compare(
-                    FloatPredicate::ONE,
-                    cond,
-                    zero_const,
-                    "loopcond",
-                );
+        // The right operand may have added blocks (nested operators...)
+        let rhs_bb = self.builder.get_insert_block().unwrap();
 
-                self.builder
-                    .build_conditional_branch(cond, loop_entry, loop_exit);
+        self.block_stack.pop();
+        self.block_stack.push(cont_bb);
+        self.reposition();
 
-                // Exit loop
-                self.block_stack.pop();
-                self.block_stack.push(loop_exit);
-                self.reposition();
+        let phi = self.builder.build_phi(self.context.bool_type(), "logictmp");
+        phi.add_incoming(&[(&known, lhs_bb), (&rhs, rhs_bb)]);
 
-                None
-            }
-            _ => unimplemented!("{:?}", node),
-        }
+        Ok(phi.as_basic_value().into_int_value())
     }
 
-    fn reposition(&mut self) {
+    /// `base ^ exp` through the `llvm.pow.f64` intrinsic
+    fn build_pow(&mut self, base: FloatValue<'ctx>, exp: FloatValue<'ctx>) -> FloatValue<'ctx> {
+        let pow = match self.get_function("llvm.pow.f64") {
+            Some(pow) => pow,
+            None => {
+                let fn_type = self
+                    .f64_type
+                    .fn_type(&[self.f64_type.into(), self.f64_type.into()], false);
+                self.module.add_function("llvm.pow.f64", fn_type, None)
+            }
+        };
         self.builder
-            .position_at_end(*self.block_stack.last().unwrap());
+            .build_call(pow, &[base.into(), exp.into()], "tmppow")
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_float_value()
     }
-}
 
-pub fn create_jit_module<'a>(context: &'a Context, string: &str) -> Module<'a> {
-    let module = context.create_module("GenKo");
+    /// `base ^ exp` by squaring, for constant exponents
+    fn build_powi(&mut self, base: BasicValueEnum<'ctx>, mut exp: u32) -> BasicValueEnum<'ctx> {
+        let mut result = None;
+        let mut square = base;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = Some(match result {
+                    Some(result) => self.build_mul(result, square, "tmppow"),
+                    None => square,
+                });
+            }
+            exp >>= 1;
+            if exp > 0 {
+                square = self.build_mul(square, square, "tmpsquare");
+            }
+        }
+        result.unwrap_or_else(|| match base {
+            BasicValueEnum::IntValue(base) => base.get_type().const_int(1, false).into(),
+            _ => self.f64_type.const_float(1.0).into(),
+        })
+    }
 
-    let builder = context.create_builder();
-    let f64_type = context.f64_type();
-    let fn_type = f64_type.fn_type(&[], false);
-    let function = module.add_function("jit", fn_type, None);
+    /// Product of two numbers of the same type
+    fn build_mul(
+        &self,
+        lhs: BasicValueEnum<'ctx>,
+        rhs: BasicValueEnum<'ctx>,
+        name: &str,
+    ) -> BasicValueEnum<'ctx> {
+        match (lhs, rhs) {
+            (BasicValueEnum::IntValue(lhs), BasicValueEnum::IntValue(rhs)) => {
+                self.builder.build_int_mul(lhs, rhs, name).into()
+            }
+            (lhs, rhs) => self
+                .builder
+                .build_float_mul(lhs.into_float_value(), rhs.into_float_value(), name)
+                .into(),
+        }
+    }
 
-    let block_stack = context.append_basic_block(function, "entry");
+    /// Operations on two integers
+    fn build_int_binary(
+        &self,
+        op: BinaryOp,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        let predicate = match op {
+            BinaryOp::Add => return Ok(self.builder.build_int_add(lhs, rhs, "tmpadd").into()),
+            BinaryOp::Sub => return Ok(self.builder.build_int_sub(lhs, rhs, "tmpsub").into()),
+            BinaryOp::Mul => return Ok(self.builder.build_int_mul(lhs, rhs, "tmpmul").into()),
+            BinaryOp::Div => {
+                return Ok(self.builder.build_int_signed_div(lhs, rhs, "tmpdiv").into())
+            }
+            BinaryOp::Modulo => {
+                return Ok(self.builder.build_int_signed_rem(lhs, rhs, "tmpmod").into())
+            }
+            BinaryOp::Eq => IntPredicate::EQ,
+            BinaryOp::Ne => IntPredicate::NE,
+            BinaryOp::Lt => IntPredicate::SLT,
+            BinaryOp::Le => IntPredicate::SLE,
+            BinaryOp::Gt => IntPredicate::SGT,
+            BinaryOp::Ge => IntPredicate::SGE,
+            BinaryOp::Pow | BinaryOp::And | BinaryOp::Or => return Err(operator(op, span)),
+        };
+        Ok(self
+            .builder
+            .build_int_compare(predicate, lhs, rhs, "tmpcmp")
+            .into())
+    }
 
-    let mut result: Option<FloatValue> = None;
+    /// Operations on two numbers, mixed operands are computed on floats
+    fn build_binary(
+        &self,
+        op: BinaryOp,
+        lhs: BasicValueEnum<'ctx>,
+        rhs: BasicValueEnum<'ctx>,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        match (lhs, rhs) {
+            (BasicValueEnum::IntValue(lhs), BasicValueEnum::IntValue(rhs)) => {
+                self.build_int_binary(op, lhs, rhs, span)
+            }
+            (lhs, rhs) => {
+                let (lhs, rhs) = (self.float(lhs), self.float(rhs));
+                self.build_float_binary(op, lhs, rhs, span)
+            }
+        }
+    }
 
-    let mut recursive_builder = RecursiveBuilder::new(
-        f64_type,
-        &context,
-        &module,
-        &builder,
-        &function,
-        block_stack,
-    );
+    /// Operations on two floats
+    fn build_float_binary(
+        &self,
+        op: BinaryOp,
+        lhs: FloatValue<'ctx>,
+        rhs: FloatValue<'ctx>,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        let predicate = match op {
+            BinaryOp::Add => return Ok(self.builder.build_float_add(lhs, rhs, "tmpadd").into()),
+            BinaryOp::Sub => return Ok(self.builder.build_float_sub(lhs, rhs, "tmpsub").into()),
+            BinaryOp::Mul => return Ok(self.builder.build_float_mul(lhs, rhs, "tmpmul").into()),
+            BinaryOp::Div => return Ok(self.builder.build_float_div(lhs, rhs, "tmpdiv").into()),
+            BinaryOp::Modulo => return Ok(self.builder.build_float_rem(lhs, rhs, "tmpmod").into()),
+            BinaryOp::Eq => FloatPredicate::UEQ,
+            BinaryOp::Ne => FloatPredicate::UNE,
+            BinaryOp::Lt => FloatPredicate::ULT,
+            BinaryOp::Le => FloatPredicate::ULE,
+            BinaryOp::Gt => FloatPredicate::UGT,
+            BinaryOp::Ge => FloatPredicate::UGE,
+            BinaryOp::Pow | BinaryOp::And | BinaryOp::Or => return Err(operator(op, span)),
+        };
+        Ok(self
+            .builder
+            .build_float_compare(predicate, lhs, rhs, "tmpcmp")
+            .into())
+    }
 
-    for node in parse(string) {
-        result = recursive_builder.build(&node);
+    /// LLVM constant of a value folded at compile time
+    fn constant(&self, value: Constant) -> BasicValueEnum<'ctx> {
+        match value {
+            Constant::Int(nb) => self.context.i64_type().const_int(nb as u64, true).into(),
+            Constant::Float(nb) => self.f64_type.const_float(nb).into(),
+            Constant::Bool(b) => self
+                .context
+                .bool_type()
+                .const_int(u64::from(b), false)
+                .into(),
+        }
     }
 
-    match result {
-        Some(r) => builder.build_return(Some(&r)),
-        _ => builder.build_return(Some(&context.f64_type().const_float(NAN))),
-    };
+    /// Folds the initializer of a global, which must be known at compile time
+    fn build_const(&mut self, node: &Node) -> Result<Constant, CodegenError> {
+        match fold(node) {
+            Some(value) => Ok(value),
+            None => Err(CodegenError::Unsupported {
+                what: String::from("global initializer which is not a constant"),
+                span: node.span,
+            }),
+        }
+    }
 
-    module
-}
+    /// Name of an identifier node, `what` describes the position for errors
+    fn ident_name<'n>(&self, ident: &'n Node, what: &str) -> Result<&'n str, CodegenError> {
+        match &ident.kind {
+            NodeKind::IdentExpr(name) => Ok(name),
+            _ => Err(CodegenError::Unsupported {
+                what: format!("{} target must be an identifier", what),
+                span: ident.span,
+            }),
+        }
+    }
 
-pub fn execute(string: &str) -> f64 {
-    let context = Context::create();
+    pub fn build(&mut self, node: &Node) -> Result<Option<BasicValueEnum<'ctx>>, CodegenError> {
+        // Resposition the "Write-Head"
+        self.reposition();
 
-    let module = create_jit_module(&context, string);
+        // Add the nodes
+        Ok(match &node.kind {
+            NodeKind::IntExpr(nb) => Some(self.constant(Constant::Int(*nb))),
+            NodeKind::NumberExpr(nb) => Some(self.constant(Constant::Float(*nb))),
+            NodeKind::BoolExpr(b) => Some(self.constant(Constant::Bool(*b))),
+            NodeKind::CastExpr { ty, expr } => {
+                let value = self.build_expr(expr)?;
+                let ty = self.llvm_type(ty).as_any_type_enum();
+                Some(self.build_cast(value, ty, expr.span)?)
+            }
+            NodeKind::IdentExpr(name) => {
+                let var = self.build_address(node)?;
+                Some(self.builder.build_load(var, name.as_str()))
+            }
+            NodeKind::IndexExpr { ident, index } => {
+                let elem = self.build_element_ptr(ident, index)?;
+                Some(self.builder.build_load(elem, "tmpload"))
+            }
 
-    // The program is wrapped into a function to use JIT (Just In Time) compilation
-    let execution_engine = module
-        .create_jit_execution_engine(OptimizationLevel::None)
-        .unwrap();
+            NodeKind::UnaryExpr {
+                op: UnaryOp::Address,
+                child,
+            } => Some(self.build_address(child)?.into()),
+            NodeKind::UnaryExpr {
+                op: UnaryOp::Deref,
+                child,
+            } => {
+                let ptr = self.build_pointer(child)?;
+                Some(self.builder.build_load(ptr, "tmpderef"))
+            }
+            NodeKind::UnaryExpr {
+                op: UnaryOp::Sub,
+                child,
+            } => match self.build_number(child)? {
+                BasicValueEnum::IntValue(child) => {
+                    Some(self.builder.build_int_neg(child, "tmpneg").into())
+                }
+                child => Some(
+                    self.builder
+                        .build_float_sub(
+                            self.f64_type.const_float(0.0),
+                            child.into_float_value(),
+                            "tmpsub",
+                        )
+                        .into(),
+                ),
+            },
+            NodeKind::UnaryExpr {
+                op: UnaryOp::Factorial,
+                child,
+            } => match fold(child) {
+                Some(nb) => Some(
+                    self.f64_type
+                        .const_float(runtime::factorial_value(nb.to_f64()))
+                        .into(),
+                ),
+                None => {
+                    let child = self.build_float(child)?;
+                    let factorial = runtime::factorial(self.context, self.module);
+                    self.builder
+                        .build_call(factorial, &[child.into()], "tmpfact")
+                        .try_as_basic_value()
+                        .left()
+                }
+            },
+            NodeKind::UnaryExpr {
+                op: UnaryOp::Not,
+                child,
+            } => {
+                let child = self.build_cond(child, "tmpcond")?;
+                Some(self.builder.build_not(child, "tmpnot").into())
+            }
+            NodeKind::BinaryExpr { op, lhs, rhs }
+                if *op == BinaryOp::And || *op == BinaryOp::Or =>
+            {
+                Some(self.build_logical(*op, lhs, rhs)?.into())
+            }
+            NodeKind::BinaryExpr {
+                op: BinaryOp::Pow,
+                lhs,
+                rhs,
+            } => {
+                let base = self.build_number(lhs)?;
+                // The type checker left the base an integer for integer powers only
+                match (base, int_exponent(rhs), fold(rhs)) {
+                    (BasicValueEnum::IntValue(_), Some(exp), _) => Some(self.build_powi(base, exp)),
+                    // Small constant integer exponents are unrolled
+                    (base, _, Some(exp))
+                        if exp.to_f64().fract() == 0.0
+                            && (0.0..=MAX_UNROLLED_POW).contains(&exp.to_f64()) =>
+                    {
+                        let base = self.float(base);
+                        Some(self.build_powi(base.into(), exp.to_f64() as u32))
+                    }
+                    (base, _, _) => {
+                        let base = self.float(base);
+                        let exp = self.build_float(rhs)?;
+                        Some(self.build_pow(base, exp).into())
+                    }
+                }
+            }
+            NodeKind::BinaryExpr { op, lhs, rhs } => {
+                let lhs = self.build_number(lhs)?;
+                let rhs = self.build_number(rhs)?;
+                if let (BasicValueEnum::IntValue(lhs), BasicValueEnum::IntValue(rhs)) = (lhs, rhs) {
+                    if *op == BinaryOp::Div || *op == BinaryOp::Modulo {
+                        self.build_division_check(lhs, rhs, node.span)?;
+                    }
+                }
+                Some(self.build_binary(*op, lhs, rhs, node.span)?)
+            }
+            NodeKind::InitExpr { ident, ty, expr } => {
+                let name = self.ident_name(ident, "declaration")?;
+                let alloca = match &expr.kind {
+                    // Arrays literals are stored element by element
+                    NodeKind::ArrayExpr(values) => {
+                        let i64_type = self.context.i64_type();
+                        let elem_type = self.llvm_type(ty.as_ref().unwrap_or(&Type::Float));
+                        let array_type = elem_type.array_type(values.len() as u32);
+                        let alloca = self.create_variable(name, array_type.into());
+
+                        // Array elements are floats unless annotated
+                        for (i, value) in values.iter().enumerate() {
+                            let span = value.span;
+                            let value = self.build_expr(value)?;
+                            let value =
+                                self.build_cast(value, elem_type.as_any_type_enum(), span)?;
+                            let index = i64_type.const_int(i as u64, false);
+                            let elem = unsafe {
+                                self.builder.build_in_bounds_gep(
+                                    alloca,
+                                    &[i64_type.const_zero(), index],
+                                    "tmpelem",
+                                )
+                            };
+                            self.builder.build_store(elem, value);
+                        }
+                        alloca
+                    }
+                    _ => {
+                        let expr = self.build_expr(expr)?;
+                        let alloca = self.create_variable(name, expr.get_type());
+
+                        self.builder.build_store(alloca, expr);
+                        alloca
+                    }
+                };
+
+                let var = self.variable(alloca);
+                self.var_stack
+                    .last_mut()
+                    .unwrap()
+                    .insert(name.to_string(), var);
+                None
+            }
+            NodeKind::ArrayInitExpr { ident, size, ty } => {
+                let name = self.ident_name(ident, "declaration")?;
+                let elem_type = self.llvm_type(ty.as_ref().unwrap_or(&Type::Float));
+                let array_type = elem_type.array_type(*size);
+                let alloca = self.create_variable(name, array_type.into());
+
+                // Arrays start zeroed, even when declared inside a loop
+                self.builder.build_store(alloca, array_type.const_zero());
+
+                let var = self.variable(alloca);
+                self.var_stack
+                    .last_mut()
+                    .unwrap()
+                    .insert(name.to_string(), var);
+                None
+            }
+            NodeKind::GlobalInitExpr { ident, ty, expr } => {
+                let name = self.ident_name(ident, "declaration")?;
+                match &expr.kind {
+                    NodeKind::ArrayExpr(values) => {
+                        let ty = ty.clone().unwrap_or(Type::Float);
+                        let values = values
+                            .iter()
+                            .map(|value| {
+                                let constant = self.build_const(value)?;
+                                match constant.cast(&ty) {
+                                    Some(constant) => Ok(self.constant(constant)),
+                                    None => Err(CodegenError::Unsupported {
+                                        what: format!("global array of {}", ty),
+                                        span: value.span,
+                                    }),
+                                }
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let array = match self.llvm_type(&ty) {
+                            BasicTypeEnum::IntType(int_type) => int_type.const_array(
+                                &values
+                                    .iter()
+                                    .map(|value| value.into_int_value())
+                                    .collect::<Vec<_>>(),
+                            ),
+                            _ => self.f64_type.const_array(
+                                &values
+                                    .iter()
+                                    .map(|value| value.into_float_value())
+                                    .collect::<Vec<_>>(),
+                            ),
+                        };
+                        let a = self
+                            .module
+                            .add_global(array.get_type(), None, &global_name(name));
+                        a.set_initializer(&array);
+                    }
+                    _ => {
+                        let value = self.build_const(expr)?;
+                        let value = self.constant(value);
+                        let a = self
+                            .module
+                            .add_global(value.get_type(), None, &global_name(name));
+                        a.set_initializer(&value);
+                    }
+                }
+                None
+            }
+            NodeKind::GlobalArrayInitExpr { ident, size, ty } => {
+                let name = self.ident_name(ident, "declaration")?;
+                let elem_type = self.llvm_type(ty.as_ref().unwrap_or(&Type::Float));
+                let array_type = elem_type.array_type(*size);
+                let a = self.module.add_global(array_type, None, &global_name(name));
+                a.set_initializer(&array_type.const_zero());
+                None
+            }
+            // Constants were substituted by the parser
+            NodeKind::ConstExpr { .. } => None,
+            NodeKind::ArrayExpr(_) => {
+                return Err(CodegenError::Unsupported {
+                    what: String::from("array literal outside of a declaration"),
+                    span: node.span,
+                })
+            }
+            NodeKind::AssignExpr { ident, expr } => {
+                let nval = self.build_expr(expr)?;
+
+                let var = match &ident.kind {
+                    NodeKind::IdentExpr(name) => match self.lookup_scalar(ident)? {
+                        Some(var) => var,
+                        None => {
+                            return Err(CodegenError::AssignToUndeclared {
+                                name: String::from(source_name(name)),
+                                span: ident.span,
+                            })
+                        }
+                    },
+                    _ => self.build_address(ident)?,
+                };
+
+                // Variables keep the type of their initializer
+                let nval = self.build_cast(nval, var.get_type().get_element_type(), expr.span)?;
+
+                self.builder.build_store(var, nval);
+                Some(nval)
+            }
+
+            NodeKind::CondExpr { cond, cons, alter } => {
+                let parent = *self.fn_stack.last().unwrap();
+
+                // create condition by comparing without 0 and returning an int
+                let cond = self.build_cond(cond, "ifcond")?;
+
+                // build branch
+                let then_bb = self.context.append_basic_block(parent, "then");
+                let else_bb = self.context.append_basic_block(parent, "else");
+                let cont_bb = self.context.append_basic_block(parent, "ifcont");
+
+                self.builder
+                    .build_conditional_branch(cond, then_bb, else_bb);
+
+                // build then block
+                self.block_stack.pop();
+                self.block_stack.push(then_bb);
+                self.reposition();
+
+                let then_val = self.build(cons)?;
+                let then_bb = self.build_branch_end(cont_bb);
+
+                // build else block
+                self.block_stack.pop();
+                self.block_stack.push(else_bb);
+                self.reposition();
+                let else_val = match alter {
+                    Some(node) => self.build(node)?,
+                    None => None,
+                };
+                let else_bb = self.build_branch_end(cont_bb);
+
+                // emit merge block
+                self.block_stack.pop();
+                self.block_stack.push(cont_bb);
+                self.reposition();
+
+                self.build_merge(&[(then_val, then_bb), (else_val, else_bb)], "iftmp")
+            }
+
+            NodeKind::CaseExpr {
+                expr,
+                arms,
+                default,
+            } => {
+                let parent = *self.fn_stack.last().unwrap();
+                let i64_type = self.context.i64_type();
+
+                let value = self.build_int(expr)?;
+
+                let arm_bbs: Vec<BasicBlock> = arms
+                    .iter()
+                    .map(|_| self.context.append_basic_block(parent, "casearm"))
+                    .collect();
+                let default_bb = self.context.append_basic_block(parent, "casedefault");
+                let cont_bb = self.context.append_basic_block(parent, "casecont");
+
+                let cases: Vec<(IntValue, BasicBlock)> = arms
+                    .iter()
+                    .zip(&arm_bbs)
+                    .map(|((label, _), bb)| (i64_type.const_int(*label as u64, true), *bb))
+                    .collect();
+                self.builder.build_switch(value, default_bb, &cases);
+
+                // Each arm, then the default, falls into the merge block
+                let mut incoming = Vec::with_capacity(arms.len() + 1);
+                let bodies = arms.iter().map(|(_, body)| Some(body));
+                for (body, bb) in bodies
+                    .chain(Some(default.as_deref()))
+                    .zip(arm_bbs.into_iter().chain(Some(default_bb)))
+                {
+                    self.block_stack.pop();
+                    self.block_stack.push(bb);
+                    self.reposition();
+
+                    let value = match body {
+                        Some(body) => self.build(body)?,
+                        None => None,
+                    };
+                    incoming.push((value, self.build_branch_end(cont_bb)));
+                }
+
+                self.block_stack.pop();
+                self.block_stack.push(cont_bb);
+                self.reposition();
+
+                self.build_merge(&incoming, "casetmp")
+            }
+
+            NodeKind::BlockExpr { nodes, .. } => {
+                let mut result = None;
+                for node in nodes {
+                    // What follows a `return` is never run
+                    if self.is_terminated() {
+                        break;
+                    }
+                    result = self.build(node)?;
+                }
+                result
+            }
+
+            NodeKind::FuncExpr {
+                ident, args, body, ..
+            } => {
+                let name = self.ident_name(ident, "function")?;
+
+                // The prototype was added by `declare_functions`
+                let (function, passing) =
+                    match (self.get_function(name), self.signatures.get(name).cloned()) {
+                        (Some(function), Some(passing)) => (function, passing),
+                        _ => {
+                            return Err(CodegenError::UnknownFunction {
+                                name: String::from(name),
+                                span: ident.span,
+                            })
+                        }
+                    };
+                let prototype = || CodegenError::Unsupported {
+                    what: format!("function `{}` which does not match its prototype", name),
+                    span: ident.span,
+                };
+
+                // Add function block
+                let entry = self.context.append_basic_block(function, "entry");
+
+                self.fn_stack.push(function);
+                self.block_stack.push(entry);
+                self.var_stack.push(HashMap::new());
+                let loops = std::mem::take(&mut self.loop_stack);
+                self.reposition();
+
+                // Build variable map, references alias the caller's storage
+                let mut params = function.get_param_iter();
+                for (arg, passing) in args.iter().zip(&passing) {
+                    let param = params.next().ok_or_else(prototype)?;
+                    param.set_name(arg.name.as_str());
+
+                    let var = match passing {
+                        Passing::Value => {
+                            let alloca =
+                                self.create_entry_block_alloca(&arg.name, param.get_type());
+                            self.builder.build_store(alloca, param);
+                            Variable::Scalar(alloca)
+                        }
+                        Passing::Ref => Variable::Scalar(param.into_pointer_value()),
+                        Passing::ArrayRef => {
+                            let len = params.next().ok_or_else(prototype)?;
+                            len.set_name(&format!("{}_len", arg.name));
+                            Variable::Array {
+                                ptr: param.into_pointer_value(),
+                                len: len.into_int_value(),
+                            }
+                        }
+                    };
+                    self.var_stack
+                        .last_mut()
+                        .unwrap()
+                        .insert(arg.name.clone(), var);
+                }
+
+                // Compile Body, the type checker made the final value an explicit return
+                self.build(body)?;
+
+                // Falling off the end of a float function gives NAN
+                if !self.is_terminated() {
+                    self.build_default_return(ident.span)?;
+                }
+
+                self.fn_stack.pop();
+                self.block_stack.pop();
+                self.var_stack.pop();
+                self.loop_stack = loops;
+
+                self.reposition();
+
+                None
+            }
+
+            NodeKind::ReturnExpr { ret } => {
+                let function = *self.fn_stack.last().unwrap();
+                let ty = match function.get_type().get_return_type() {
+                    Some(ty) => ty.as_any_type_enum(),
+                    None => self.f64_type.as_any_type_enum(),
+                };
+                let value = self.build_expr(ret)?;
+                let value = self.build_cast(value, ty, ret.span)?;
+                self.builder.build_return(Some(&value));
+                None
+            }
+
+            NodeKind::BreakExpr | NodeKind::ContinueExpr => {
+                let (next, exit) = match self.loop_stack.last() {
+                    Some(targets) => *targets,
+                    None => {
+                        return Err(CodegenError::Unsupported {
+                            what: String::from("`break` or `continue` outside of a loop"),
+                            span: node.span,
+                        })
+                    }
+                };
+                let target = match node.kind {
+                    NodeKind::BreakExpr => exit,
+                    _ => next,
+                };
+                self.builder.build_unconditional_branch(target);
+                None
+            }
+
+            NodeKind::CallExpr { ident, args } if ident.is_builtin_len() => {
+                if args.len() != 1 {
+                    return Err(CodegenError::ArityMismatch {
+                        name: String::from("len"),
+                        expected: 1,
+                        found: args.len(),
+                        span: node.span,
+                    });
+                }
+                let (_, len) = self.lookup_array(&args[0])?;
+                Some(len.into())
+            }
+            NodeKind::CallExpr { ident, args } => {
+                let name = self.ident_name(ident, "call")?;
+                match (self.get_function(name), self.signatures.get(name).cloned()) {
+                    (Some(fun), Some(passing)) => {
+                        if passing.len() != args.len() {
+                            return Err(CodegenError::ArityMismatch {
+                                name: String::from(name),
+                                expected: passing.len(),
+                                found: args.len(),
+                                span: node.span,
+                            });
+                        }
+
+                        let params = fun.get_type().get_param_types();
+                        let mut argsv: Vec<BasicValueEnum> = Vec::with_capacity(params.len());
+
+                        for (arg, passing) in args.iter().zip(passing) {
+                            let values = match passing {
+                                Passing::Value => vec![self.build_expr(arg)?],
+                                Passing::Ref => vec![self.build_address(arg)?.into()],
+                                Passing::ArrayRef => {
+                                    let (ptr, len) = self.build_array_ref(arg)?;
+                                    vec![ptr.into(), len.into()]
+                                }
+                            };
+                            for value in values {
+                                let ty = params[argsv.len()].as_any_type_enum();
+                                argsv.push(self.build_cast(value, ty, arg.span)?);
+                            }
+                        }
+
+                        let value = match self
+                            .builder
+                            .build_call(fun, argsv.as_slice(), "tmp")
+                            .try_as_basic_value()
+                            .left()
+                        {
+                            Some(value) => value,
+                            None => {
+                                return Err(CodegenError::Unsupported {
+                                    what: format!("call to `{}` without a result", name),
+                                    span: node.span,
+                                })
+                            }
+                        };
+                        self.build_failure_check(node.span)?;
+                        Some(value)
+                    }
+                    _ => {
+                        return Err(CodegenError::UnknownFunction {
+                            name: String::from(name),
+                            span: ident.span,
+                        })
+                    }
+                }
+            }
+
+            NodeKind::WhileExpr {
+                cond: condexpr,
+                body,
+            } => {
+                let parent = *self.fn_stack.last().unwrap();
+
+                // build branch, `continue` jumps to the condition in `loopnext`
+                let loop_entry = self.context.append_basic_block(parent, "loop");
+                let loop_next = self.context.append_basic_block(parent, "loopnext");
+                let loop_exit = self.context.append_basic_block(parent, "exitloop");
+
+                // Loop condition
+                let cond = self.build_cond(condexpr, "loopcond")?;
+
+                self.builder
+                    .build_conditional_branch(cond, loop_entry, loop_exit);
+
+                self.block_stack.pop();
+                self.block_stack.push(loop_entry);
+                self.reposition();
+
+                self.loop_stack.push((loop_next, loop_exit));
+                let body = self.build(body);
+                self.loop_stack.pop();
+                body?;
+
+                // Reloop, unless the body jumped away
+                if !self.is_terminated() {
+                    self.builder.build_unconditional_branch(loop_next);
+                }
+
+                self.block_stack.pop();
+                self.block_stack.push(loop_next);
+                self.reposition();
+
+                let cond = self.build_cond(condexpr, "loopcond")?;
+                self.builder
+                    .build_conditional_branch(cond, loop_entry, loop_exit);
+
+                // Exit loop
+                self.block_stack.pop();
+                self.block_stack.push(loop_exit);
+                self.reposition();
+
+                None
+            }
+
+            // Counted loop in the shape LLVM recognises: the bounds and the step are
+            // computed once, and the variable only moves in `fornext`
+            NodeKind::ForExpr {
+                ident,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                let name = self.ident_name(ident, "loop variable")?;
+                let parent = *self.fn_stack.last().unwrap();
+
+                let start = self.build_number(start)?;
+                let ty = start.get_type();
+                let span = end.span;
+                let end = self.build_number(end)?;
+                let end = self.build_cast(end, ty.as_any_type_enum(), span)?;
+                let step = match step {
+                    Some(step) => {
+                        let span = step.span;
+                        let value = self.build_number(step)?;
+                        Some(self.build_cast(value, ty.as_any_type_enum(), span)?)
+                    }
+                    None => None,
+                };
+
+                let var = self.create_variable(name, ty);
+                self.builder.build_store(var, start);
+                self.var_stack
+                    .last_mut()
+                    .unwrap()
+                    .insert(name.to_string(), Variable::Scalar(var));
+
+                let loop_cond = self.context.append_basic_block(parent, "forcond");
+                let loop_entry = self.context.append_basic_block(parent, "for");
+                let loop_next = self.context.append_basic_block(parent, "fornext");
+                let loop_exit = self.context.append_basic_block(parent, "exitfor");
+                self.builder.build_unconditional_branch(loop_cond);
+
+                // Loop condition, `end` is excluded
+                self.block_stack.pop();
+                self.block_stack.push(loop_cond);
+                self.reposition();
+                let current = self.builder.build_load(var, name);
+                let below = self
+                    .build_binary(BinaryOp::Lt, current, end, span)?
+                    .into_int_value();
+                let cond = match step {
+                    // Negative steps count down, folded away when the step is constant
+                    Some(step) => {
+                        let above = self.build_binary(BinaryOp::Gt, current, end, span)?;
+                        let up = self.build_binary(BinaryOp::Gt, step, zero(ty), span)?;
+                        let down = self.build_binary(BinaryOp::Lt, step, zero(ty), span)?;
+                        let up = self.builder.build_and(below, up.into_int_value(), "forup");
+                        let down = self.builder.build_and(
+                            above.into_int_value(),
+                            down.into_int_value(),
+                            "fordown",
+                        );
+                        self.builder.build_or(up, down, "forcond")
+                    }
+                    None => below,
+                };
+                self.builder
+                    .build_conditional_branch(cond, loop_entry, loop_exit);
+
+                self.block_stack.pop();
+                self.block_stack.push(loop_entry);
+                self.reposition();
+
+                self.loop_stack.push((loop_next, loop_exit));
+                let body = self.build(body);
+                self.loop_stack.pop();
+                body?;
+
+                if !self.is_terminated() {
+                    self.builder.build_unconditional_branch(loop_next);
+                }
+
+                // Step the variable
+                self.block_stack.pop();
+                self.block_stack.push(loop_next);
+                self.reposition();
+                let current = self.builder.build_load(var, name);
+                let step = match step {
+                    Some(step) => step,
+                    None => {
+                        let one = self.constant(Constant::Int(1));
+                        self.build_cast(one, ty.as_any_type_enum(), node.span)?
+                    }
+                };
+                let next = self.build_binary(BinaryOp::Add, current, step, node.span)?;
+                self.builder.build_store(var, next);
+                self.builder.build_unconditional_branch(loop_cond);
+
+                self.block_stack.pop();
+                self.block_stack.push(loop_exit);
+                self.reposition();
+
+                None
+            }
+        })
+    }
+
+    fn reposition(&mut self) {
+        self.builder
+            .position_at_end(*self.block_stack.last().unwrap());
+    }
+}
+
+/// Runs the LLVM verifier on `module`, naming the first invalid function
+///
+/// `spans` locates the Gengo functions, the others are the top-level code which
+/// is reported at `top_level`, its first node
+fn verify(
+    module: &Module,
+    spans: &HashMap<String, Span>,
+    top_level: Span,
+) -> Result<(), CodegenError> {
+    let message = match module.verify() {
+        Ok(()) => return Ok(()),
+        Err(message) => message.to_string().trim().to_string(),
+    };
+
+    let mut function = module.get_first_function();
+    while let Some(value) = function {
+        if !value.verify(false) {
+            let name = value.get_name().to_string_lossy().into_owned();
+            return Err(CodegenError::InvalidIr {
+                span: spans.get(&name).copied().unwrap_or(top_level),
+                function: spans.get(&name).map(|_| name),
+                message,
+                ir: value.print_to_string().to_string(),
+            });
+        }
+        function = value.get_next_function();
+    }
+
+    // Not within a function, like a global
+    Err(CodegenError::InvalidIr {
+        function: None,
+        message,
+        ir: module.print_to_string().to_string(),
+        span: top_level,
+    })
+}
+
+/// Adds to `names` the variables used in the functions defined in `node`
+fn captures(node: &Node, in_function: bool, names: &mut HashSet<String>) {
+    match &node.kind {
+        NodeKind::FuncExpr { body, .. } => return captures(body, true, names),
+        NodeKind::IdentExpr(name) if in_function => {
+            names.insert(name.clone());
+        }
+        _ => (),
+    }
+    for child in node.children() {
+        captures(child, in_function, names);
+    }
+}
+
+/// Name of the LLVM global holding the variable `name`
+fn global_name(name: &str) -> String {
+    format!("{}{}", VARIABLE_PREFIX, name)
+}
+
+/// Zero value of `ty`, used to initialize globals
+fn zero(ty: BasicTypeEnum) -> BasicValueEnum {
+    match ty {
+        BasicTypeEnum::ArrayType(ty) => ty.const_zero().into(),
+        BasicTypeEnum::FloatType(ty) => ty.const_zero().into(),
+        BasicTypeEnum::IntType(ty) => ty.const_zero().into(),
+        BasicTypeEnum::PointerType(ty) => ty.const_null().into(),
+        BasicTypeEnum::StructType(ty) => ty.const_zero().into(),
+        BasicTypeEnum::VectorType(ty) => ty.const_zero().into(),
+    }
+}
+
+fn is_array(ptr: PointerValue) -> bool {
+    ptr.get_type().get_element_type().is_array_type()
+}
+
+fn is_bool(value: IntValue) -> bool {
+    value.get_type().get_bit_width() == 1
+}
+
+/// Name of a Gengo type in diagnostics
+fn type_name(ty: AnyTypeEnum) -> String {
+    match ty {
+        AnyTypeEnum::IntType(ty) if ty.get_bit_width() == 1 => String::from("bool"),
+        AnyTypeEnum::IntType(_) => String::from("int"),
+        AnyTypeEnum::FloatType(_) => String::from("float"),
+        AnyTypeEnum::PointerType(ptr) => {
+            format!("pointer to {}", type_name(ptr.get_element_type()))
+        }
+        AnyTypeEnum::ArrayType(_) => String::from("array"),
+        _ => String::from("value"),
+    }
+}
+
+/// `^`, `&&` and `||` have their own lowering, they are not plain operations
+fn operator(op: BinaryOp, span: Span) -> CodegenError {
+    CodegenError::Unsupported {
+        what: format!("{:?} as an arithmetic or comparison operator", op),
+        span,
+    }
+}
+
+fn mismatch(expected: &str, value: BasicValueEnum, span: Span) -> CodegenError {
+    CodegenError::TypeMismatch {
+        expected: String::from(expected),
+        found: type_name(value.get_type().as_any_type_enum()),
+        span,
+    }
+}
+
+pub fn create_jit_module<'a>(
+    context: &'a Context,
+    nodes: &[Node],
+    options: &CodegenOptions,
+) -> Result<Module<'a>, CodegenError> {
+    let module = context.create_module("GenKo");
+
+    let builder = context.create_builder();
+    let f64_type = context.f64_type();
+    let fn_type = f64_type.fn_type(&[], false);
+    let function = module.add_function(runtime::ENTRY, fn_type, None);
+
+    let block_stack = context.append_basic_block(function, "entry");
+
+    let mut result = None;
+
+    let mut recursive_builder = RecursiveBuilder::new(
+        f64_type,
+        context,
+        &module,
+        &builder,
+        &function,
+        block_stack,
+        options,
+    );
+
+    // Names are unique, so the top-level variables named in functions are captured
+    for node in nodes {
+        captures(node, false, &mut recursive_builder.captured);
+        recursive_builder.declare_functions(node)?;
+    }
+    for node in nodes {
+        if recursive_builder.is_terminated() {
+            break;
+        }
+        result = recursive_builder.build(node)?;
+    }
+
+    // A top-level `return` already ended the program
+    if !recursive_builder.is_terminated() {
+        match result {
+            Some(value @ BasicValueEnum::IntValue(_))
+            | Some(value @ BasicValueEnum::FloatValue(_)) => {
+                let r = recursive_builder.float(value);
+                builder.build_return(Some(&r))
+            }
+            _ => builder.build_return(Some(&context.f64_type().const_float(NAN))),
+        };
+    }
+
+    let top_level = nodes.first().map_or_else(Span::default, |node| node.span);
+    verify(&module, &recursive_builder.spans, top_level)?;
+    Ok(module)
+}
+
+pub fn execute(string: &str) -> Result<f64, CodegenError> {
+    execute_with(string, &CodegenOptions::default())
+}
+
+pub fn execute_with(string: &str, options: &CodegenOptions) -> Result<f64, CodegenError> {
+    let nodes = parse("<execute>", string)
+        .and_then(|nodes| resolve("<execute>", nodes))
+        .and_then(|nodes| check("<execute>", nodes))
+        .map_err(CodegenError::Rejected)?;
+
+    let context = Context::create();
+
+    let module = create_jit_module(&context, &nodes, options)?;
 
     // Uncomment to print LLVMIR Code
     // module.print_to_stderr();
 
-    unsafe {
-        let jit_function: JitFunction<JitFunc> = execution_engine.get_function("jit").unwrap();
-        jit_function.call()
+    run(&module)
+}
+
+/// Runs the top-level code of `module`, with the runtime linked in
+///
+/// Runtime errors stop the program and are returned
+pub fn run(module: &Module) -> Result<f64, CodegenError> {
+    // The program is wrapped into a function to use JIT (Just In Time) compilation
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .map_err(|message| CodegenError::Jit(message.to_string()))?;
+    runtime::link(&execution_engine, module);
+
+    unsafe {
+        let jit_function: JitFunction<JitFunc> = execution_engine
+            .get_function(runtime::ENTRY)
+            .map_err(|_| CodegenError::Jit(format!("no entry point `{}`", runtime::ENTRY)))?;
+        let result = jit_function.call();
+        match runtime::take_error() {
+            Some(message) => Err(CodegenError::Runtime(message)),
+            None => Ok(result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod codegen {
+    use super::{
+        create_jit_module, execute, execute_with, verify, CodegenError, CodegenOptions, Span,
+    };
+    use crate::parser::parse;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use inkwell::context::Context;
+    use std::collections::HashMap;
+
+    #[test]
+    fn float() {
+        assert_eq!(execute("1"), Ok(1.0))
+    }
+
+    #[test]
+    fn not() {
+        assert_eq!(execute("!false"), Ok(1.0))
+    }
+
+    #[test]
+    fn not_number() {
+        assert_eq!(execute("!2 + !0"), Ok(1.0))
+    }
+
+    #[test]
+    fn bool_variable() {
+        assert_eq!(
+            execute("let b = 1 < 2; let n = 0; if b && !false then { n = 2; } b = 0; n + b"),
+            Ok(2.0)
+        )
+    }
+
+    #[test]
+    fn bool_true() {
+        assert_eq!(execute("true"), Ok(1.0))
+    }
+
+    #[test]
+    fn bool_false() {
+        assert_eq!(execute("false"), Ok(0.0))
+    }
+
+    #[test]
+    fn add() {
+        assert_eq!(execute("1+2"), Ok(3.0))
+    }
+
+    #[test]
+    fn unary_sub() {
+        assert_eq!(execute("let a=1; -a"), Ok(-1.0))
+    }
+
+    #[test]
+    fn int_division() {
+        assert_eq!(execute("7 / 2"), Ok(3.0));
+        assert_eq!(execute("7 / 2.0"), Ok(3.5));
+        assert_eq!(execute("-7 % 3"), Ok(-1.0));
+        assert_eq!(execute("7.5 % 2"), Ok(1.5));
+        // Checked divisions by variables
+        assert_eq!(
+            execute("let a = -7; let b = 2; a / b * 10 + a % b"),
+            Ok(-31.0)
+        );
+        assert_eq!(execute("let z = 0; 1.0 / z"), Ok(f64::INFINITY));
+    }
+
+    #[test]
+    fn int_large_modulo() {
+        assert_eq!(execute("9007199254740993 % 10"), Ok(3.0))
+    }
+
+    #[test]
+    fn int_conversion() {
+        assert_eq!(execute("let a = 1; a = 2.7; a"), Ok(2.0));
+        assert_eq!(execute("fn half(x) { return x / 2; } half(3)"), Ok(1.5));
+    }
+
+    #[test]
+    fn annotations() {
+        assert_eq!(
+            execute(
+                "fn gcd(a: int, b: int) -> int {
+                    while b != 0 { let t = b; b = a % b; a = t; }
+                    return a;
+                }
+                gcd(84, 36) / 5"
+            ),
+            Ok(2.0)
+        );
+        assert_eq!(execute("let x: float = 7; x / 2"), Ok(3.5));
+        assert_eq!(
+            execute("fn inc(ref a: int) -> int { a = a + 1; return a; } let x = 1; inc(x); x"),
+            Ok(2.0)
+        );
+    }
+
+    #[test]
+    fn int_array() {
+        assert_eq!(
+            execute(
+                "fn last(ref t: [int]) -> int { return t[len(t) - 1]; }
+                let t: int = [1, 2.5, 7]; last(t) / 2 + t[1]"
+            ),
+            Ok(5.0)
+        );
+        assert_eq!(
+            execute(
+                "fn last(ref t: [int]) -> int { return t[len(t) - 1]; }
+                let t[3]: int; global g[2]: int;
+                t[2] = 7.9; g[1] = t[2] / 2; last(t) * 10 + last(g)"
+            ),
+            Ok(73.0)
+        );
+        match execute("fn last(ref t: [int]) -> int { return t[0]; } let t = [1]; last(t)") {
+            Err(CodegenError::Rejected(errors)) => assert_eq!(
+                errors[0].message,
+                "mismatched types: expected array of int, found array of float"
+            ),
+            result => panic!("unexpected {:?}", result),
+        }
+        // Annotated references are arrays only when annotated as such
+        match execute("fn first(ref t: int) -> int { return t[0]; } let x = 1; first(x)") {
+            Err(CodegenError::Unsupported { what, .. }) => {
+                assert_eq!(what, "indexing scalar `t`")
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn forwarded_array() {
+        assert_eq!(
+            execute(
+                "fn sum(ref t) { let s = 0.0; for i in 0..len(t) { s = s + t[i]; } s }
+                fn outer(ref t: [float]) { return sum(t); }
+                let arr = [1, 2, 3.5]; outer(arr)"
+            ),
+            Ok(6.5)
+        );
+        assert_eq!(
+            execute(
+                "fn first(ref t: [int]) -> int { return t[0]; }
+                fn forward(ref t: [int]) -> int { return first(t) + len(t); }
+                let arr: int = [4, 5]; forward(arr)"
+            ),
+            Ok(6.0)
+        );
+    }
+
+    #[test]
+    fn int_pointer_mismatch() {
+        match execute("fn inc(ref a) { a = a + 1; return a; } let x = 1; inc(x)") {
+            Err(CodegenError::Rejected(errors)) => assert_eq!(
+                errors[0].message,
+                "mismatched types: expected pointer to float, found pointer to int"
+            ),
+            r => panic!("expected a type mismatch, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn modulo() {
+        assert_eq!(execute("10 % 3"), Ok(1.0))
+    }
+
+    #[test]
+    fn cmp_lt() {
+        assert_eq!(execute("2 < 1"), Ok(0.0))
+    }
+
+    #[test]
+    fn cmp_ge() {
+        assert_eq!(execute("1 >= 1"), Ok(1.0))
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(execute("let a = 2+2; a"), Ok(4.0))
     }
-}
 
-#[cfg(test)]
-mod codegen {
-    use super::execute;
+    #[test]
+    fn fn_decl() {
+        assert_eq!(execute("fn test() {return 1;} 10"), Ok(10.0))
+    }
 
     #[test]
-    fn float() {
-        assert_eq!(execute("1"), 1.0)
+    fn fn_args() {
+        assert_eq!(execute("fn test(a) {return 10+a;} test(5)"), Ok(15.0))
     }
 
     #[test]
-    fn not() {
-        assert_eq!(execute("!false"), 1.0)
+    fn fn_local() {
+        assert_eq!(execute("let a=5; fn test() {let a=10;} test(); a"), Ok(5.0))
     }
 
     #[test]
-    fn bool_true() {
-        assert_eq!(execute("true"), 1.0)
+    fn implicit_return() {
+        assert_eq!(execute("fn double(a) { a * 2 } double(4)"), Ok(8.0));
+        assert_eq!(
+            execute("fn abs(a: int) -> int { if a > 0 then { a } else { -a } } abs(-3)"),
+            Ok(3.0)
+        );
+        assert!(execute("fn nothing() { let a = 1; } nothing()")
+            .unwrap()
+            .is_nan());
     }
 
     #[test]
-    fn bool_false() {
-        assert_eq!(execute("false"), 0.0)
+    fn dead_code() {
+        assert_eq!(
+            execute("fn f() { return 1; let a = 2; a = 3; } f() + 1"),
+            Ok(2.0)
+        );
+        assert_eq!(execute("return 5; 6"), Ok(5.0));
     }
 
     #[test]
-    fn add() {
-        assert_eq!(execute("1+2"), 3.0)
+    fn early_return() {
+        assert_eq!(
+            execute(
+                "fn find(ref t, x) -> int {
+                    let i = 0;
+                    while i < len(t) {
+                        if t[i] == x then { return i; }
+                        i = i + 1;
+                    }
+                    return -1;
+                }
+                let t = [4, 8, 15, 16, 23, 42];
+                find(t, 16) * 10 + find(t, 5)"
+            ),
+            Ok(29.0)
+        );
+        assert_eq!(
+            execute(
+                "fn sign(a) -> int {
+                    if a < 0 then { return -1; } elif a == 0 then { return 0; } else { return 1; }
+                }
+                sign(-5) + sign(3) * 10 + sign(0)"
+            ),
+            Ok(9.0)
+        );
+        assert_eq!(
+            execute(
+                "fn f(n: int) -> int { case n { 1 => { return 10; }, _ => { n } } }
+                fn first(ref t) { while true { return t[0]; } }
+                let t = [7]; f(1) + f(2) + first(t)"
+            ),
+            Ok(19.0)
+        );
     }
 
     #[test]
-    fn unary_sub() {
-        assert_eq!(execute("let a=1; -a"), -1.0)
+    fn break_continue() {
+        assert_eq!(
+            execute(
+                "let s = 0; let i = 0;
+                while i < 10 {
+                    i = i + 1;
+                    if i % 2 == 0 then { continue; }
+                    if i > 7 then { break; }
+                    s = s + i;
+                }
+                s * 100 + i"
+            ),
+            Ok(1609.0)
+        );
+        assert_eq!(
+            execute(
+                "fn pairs(n) -> int {
+                    let c = 0; let i = 0;
+                    while true {
+                        i = i + 1;
+                        if i > n then { break; }
+                        let j = 0;
+                        while 1 {
+                            j = j + 1;
+                            if j == i then { break; } else { c = c + 1; continue; }
+                        }
+                    }
+                    c
+                }
+                pairs(4)"
+            ),
+            Ok(6.0)
+        );
+        match execute("while 1 { fn f() { break; } }") {
+            Err(CodegenError::Rejected(errors)) => {
+                assert_eq!(errors[0].message, "`break` outside of a loop")
+            }
+            r => panic!("expected a rejected break, got {:?}", r),
+        }
     }
 
     #[test]
-    fn modulo() {
-        assert_eq!(execute("10 % 3"), 1.0)
+    fn for_range() {
+        assert_eq!(
+            execute("let s = 0; for i in 0..5 { s = s + i; } s"),
+            Ok(10.0)
+        );
+        assert_eq!(
+            execute("let s = 0; for i in 1..10 step 3 { s = s * 10 + i; } s"),
+            Ok(147.0)
+        );
+        assert_eq!(
+            execute("let s = 0; for i in 3..0 step -1 { s = s * 10 + i; } s"),
+            Ok(321.0)
+        );
+        assert_eq!(
+            execute("let s = 0.0; for x in 0..1 step 0.25 { s = s + x; } s"),
+            Ok(1.5)
+        );
+        assert_eq!(
+            execute(
+                "fn sum(ref t) {
+                    let s = 0;
+                    for i in 0..len(t) {
+                        if t[i] < 0 then { continue; }
+                        if t[i] > 100 then { break; }
+                        s = s + t[i];
+                    }
+                    s
+                }
+                let t = [1, -2, 3, 500, 4]; let i = 7;
+                for i in 0..2 { } sum(t) + i"
+            ),
+            Ok(11.0)
+        );
+        assert_eq!(
+            execute("let s = 0; let d = -2; for i in 5..0 step d { s = s * 10 + i; } s"),
+            Ok(531.0)
+        );
+        match execute("for i in 0..3 { i = 5; }") {
+            Err(CodegenError::Rejected(errors)) => {
+                assert_eq!(errors[0].message, "cannot assign to loop variable `i`")
+            }
+            r => panic!("expected a rejected assignment, got {:?}", r),
+        }
+        match execute("for i in 0..3 step 0 {}") {
+            Err(CodegenError::Rejected(errors)) => {
+                assert_eq!(errors[0].message, "the step of a range cannot be zero")
+            }
+            r => panic!("expected a rejected step, got {:?}", r),
+        }
     }
 
     #[test]
-    fn cmp_lt() {
-        assert_eq!(execute("2 < 1"), 0.0)
+    fn invalid_ir() {
+        let context = Context::create();
+        let module = context.create_module("test");
+        let fn_type = context.f64_type().fn_type(&[], false);
+        let function = module.add_function("broken", fn_type, None);
+        // A block without terminator
+        context.append_basic_block(function, "entry");
+
+        let top_level = Span {
+            start: 0,
+            end: 3,
+            line: 1,
+            col: 1,
+        };
+        let mut spans = HashMap::new();
+        spans.insert(String::from("broken"), Span::default());
+        match verify(&module, &spans, top_level) {
+            Err(CodegenError::InvalidIr { function, ir, .. }) => {
+                assert_eq!(function.as_deref(), Some("broken"));
+                assert!(ir.contains("@broken"));
+            }
+            r => panic!("expected invalid IR, got {:?}", r),
+        }
+
+        // The top-level code is reported at its first node
+        match verify(&module, &HashMap::new(), top_level) {
+            Err(CodegenError::InvalidIr { function, span, .. }) => {
+                assert_eq!(function, None);
+                assert_eq!(span, top_level);
+            }
+            r => panic!("expected invalid IR, got {:?}", r),
+        }
     }
 
     #[test]
-    fn cmp_ge() {
-        assert_eq!(execute("1 >= 1"), 1.0)
+    fn captured_globals() {
+        let ir = module_ir("let a = 1; let b = 2; fn f() { return a; } for i in 0..b { } f() + b");
+        assert!(ir.contains("@var.a = "));
+        assert!(!ir.contains("@var.b = "));
+        assert!(!ir.contains("@var.i = "));
     }
 
     #[test]
-    fn variables() {
-        assert_eq!(execute("let a = 2+2; a"), 4.0)
+    fn global_names() {
+        // Variables do not clash with the runtime, the entry point or functions
+        assert_eq!(
+            execute("global gengo_factorial = 1; let n = 3; n! + gengo_factorial"),
+            Ok(7.0)
+        );
+        assert_eq!(execute("global jit = 1; jit"), Ok(1.0));
+        assert_eq!(
+            execute("global f = 1; fn f() { return f + 1; } f + f()"),
+            Ok(3.0)
+        );
     }
 
     #[test]
-    fn fn_decl() {
-        assert_eq!(execute("fn test() {return 1;} 10"), 10.0)
+    fn source_names() {
+        match execute("let t = 1; { let t = 2; t[0] }") {
+            Err(CodegenError::Unsupported { what, .. }) => {
+                assert_eq!(what, "indexing scalar `t`")
+            }
+            r => panic!("expected an unsupported indexing, got {:?}", r),
+        }
     }
 
     #[test]
-    fn fn_args() {
-        assert_eq!(execute("fn test(a) {return 10+a;} test(5)"), 15.0)
+    fn block_scope() {
+        assert_eq!(
+            execute("let a = 1; { let a = 2.5; a = a * 2; } let i = 0; while i < 3 { let a = i; i = i + 1; } a"),
+            Ok(1.0)
+        )
     }
 
     #[test]
-    fn fn_local() {
-        assert_eq!(execute("let a=5; fn test() {let a=10;} test(); a"), 5.0)
+    fn fn_reads_top_level() {
+        assert_eq!(
+            execute("let n = 3; let t = [1, 2, 4]; fn f(x) { n = n + 1; return x * n + t[2]; } f(2) + n"),
+            Ok(16.0)
+        )
     }
 
     #[test]
-    #[should_panic]
     fn fn_invalid_params() {
-        execute("fn test(a) {} test()");
+        match execute("fn test(a) {} test()") {
+            Err(CodegenError::ArityMismatch {
+                expected, found, ..
+            }) => assert_eq!((expected, found), (1, 0)),
+            r => panic!("expected an arity mismatch, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn undefined_variable() {
+        match execute("let a = 1;\na + b") {
+            Err(CodegenError::Rejected(errors)) => {
+                assert_eq!(errors[0].message, "undefined variable `b`");
+                assert_eq!((errors[0].span.line, errors[0].span.col), (2, 5));
+            }
+            r => panic!("expected an undefined variable, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn unknown_function() {
+        match execute("nope(1)") {
+            Err(CodegenError::UnknownFunction { name, .. }) => assert_eq!(name, "nope"),
+            r => panic!("expected an unknown function, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn assign_undeclared() {
+        match execute("a = 1") {
+            Err(CodegenError::Rejected(errors)) => assert_eq!(
+                errors[0].message,
+                "cannot assign to undeclared variable `a`"
+            ),
+            r => panic!("expected an undeclared assignment, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn syntax_error() {
+        match execute("let a = ;") {
+            Err(CodegenError::Rejected(errors)) => assert_eq!(errors.len(), 1),
+            r => panic!("expected a syntax error, got {:?}", r),
+        }
     }
 
     #[test]
     fn if_then_cond() {
-        assert_eq!(execute("let a=1; if (1 == 1) then {a = 3;} a"), 3.0)
+        assert_eq!(execute("let a=1; if (1 == 1) then {a = 3;} a"), Ok(3.0))
     }
 
     #[test]
     fn if_then_else_cond() {
         assert_eq!(
             execute("let a=1; if (0 == 1) then {a = 3;} else {a=2;} a"),
-            2.0
+            Ok(2.0)
         )
     }
 
     #[test]
     fn if_then_else_cond_empty() {
-        assert_eq!(execute("let a=1; if (0 == 1) then {} else {} a"), 1.0)
+        assert_eq!(execute("let a=1; if (0 == 1) then {} else {} a"), Ok(1.0))
     }
 
     #[test]
@@ -644,15 +2467,52 @@ mod codegen {
             execute(
                 "fn test(a) { let b=0; if a then {b=test(a-1);} else {b=a;} return b;} test(10)"
             ),
-            0.0
+            Ok(0.0)
+        )
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        assert_eq!(
+            execute(
+                "fn is_even(n: int) -> bool { let r = true; if n != 0 then { r = is_odd(n - 1); } return r; }
+                fn is_odd(n: int) -> bool { let r = false; if n != 0 then { r = is_even(n - 1); } return r; }
+                is_even(10) + is_odd(10)"
+            ),
+            Ok(1.0)
         )
     }
 
+    #[test]
+    fn forward_call() {
+        assert_eq!(
+            execute("let a = twice(4); fn twice(x) { return 2 * x; } a"),
+            Ok(8.0)
+        );
+        match execute("fn f() { return 1; } fn f() { return 2; } f()") {
+            Err(CodegenError::FunctionRedefinition { name, span }) => {
+                assert_eq!(name, "f");
+                assert_eq!((span.line, span.col), (1, 25));
+            }
+            r => panic!("expected a redefinition, got {:?}", r),
+        }
+        for name in &["jit", "gengo_factorial", "gengo_bounds_error", "len"] {
+            let source = format!("fn {}(a) {{ return a; }} {}(1)", name, name);
+            match execute(&source) {
+                Err(CodegenError::ReservedName { name: found, span }) => {
+                    assert_eq!(found, *name);
+                    assert_eq!((span.line, span.col), (1, 4));
+                }
+                r => panic!("expected a reserved name, got {:?}", r),
+            }
+        }
+    }
+
     #[test]
     fn while_loop() {
         assert_eq!(
             execute("let a=2; let b=0; while (a!=0) {a=a-1; b=b+1;} b"),
-            2.0
+            Ok(2.0)
         )
     }
 
@@ -660,7 +2520,266 @@ mod codegen {
     fn global_var() {
         assert_eq!(
             execute("global a=2; a=3; fn test() {return a;} test()"),
-            3.0
+            Ok(3.0)
+        )
+    }
+
+    #[test]
+    fn array() {
+        assert_eq!(
+            execute("let t[3]; t[0] = 1; t[1] = 2; t[2] = t[0] + t[1]; t[2]"),
+            Ok(3.0)
+        )
+    }
+
+    #[test]
+    fn array_zeroed() {
+        assert_eq!(execute("let t[3]; t[1]"), Ok(0.0))
+    }
+
+    #[test]
+    fn array_without_index() {
+        match execute("let t[3]; t + 1") {
+            Err(CodegenError::Unsupported { .. }) => (),
+            r => panic!("expected an unsupported use of an array, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn sieve() {
+        assert_eq!(
+            execute(include_str!("../examples/eratosthene.gengo")),
+            Ok(25.0)
+        )
+    }
+
+    #[test]
+    fn array_literal() {
+        assert_eq!(execute("let t = [1, 2, 3]; t[0] + t[2]"), Ok(4.0))
+    }
+
+    #[test]
+    fn global_array() {
+        assert_eq!(
+            execute("global t[10]; fn set(i) { t[i] = i; return i; } set(4); t[4] + t[3]"),
+            Ok(4.0)
+        )
+    }
+
+    #[test]
+    fn global_array_literal() {
+        assert_eq!(
+            execute("global t = [1, -2, true]; fn get(i) { return t[i]; } get(1) + get(2)"),
+            Ok(-1.0)
+        )
+    }
+
+    #[test]
+    fn global_not_constant() {
+        match execute("global a = 1; global b = a;") {
+            Err(CodegenError::Unsupported { .. }) => (),
+            r => panic!("expected a non constant initializer, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn array_bounds_check() {
+        assert_eq!(
+            execute("let t[10]; let i = 0; while i < 10 { t[i] = i; i = i + 1; } t[9]"),
+            Ok(9.0)
+        )
+    }
+
+    /// IR of the module compiled from `source`
+    fn module_ir(source: &str) -> String {
+        let nodes = parse("test", source)
+            .and_then(|nodes| resolve("test", nodes))
+            .and_then(|nodes| check("test", nodes))
+            .unwrap();
+        let context = Context::create();
+        let module = create_jit_module(&context, &nodes, &CodegenOptions::default()).unwrap();
+        module.print_to_string().to_string()
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let error = |message: &str| Err(CodegenError::Runtime(String::from(message)));
+        assert_eq!(
+            execute("let t[2];\nt[2]"),
+            error("line 2: index 2 is out of bounds for array `t` of length 2")
+        );
+        assert_eq!(
+            execute("let z = 0; 1 / z"),
+            error("line 1: division by zero")
+        );
+        // Callers stop too, the loop would never end otherwise
+        assert_eq!(
+            execute(
+                "fn get(ref t, i) { return t[i]; }
+                let t = [1]; let i = 0; while get(t, i) { i = i + 1; } i"
+            ),
+            error("line 1: index 1 is out of bounds for array `t` of length 1")
+        );
+        // The next program runs normally
+        assert_eq!(execute("let t[2]; t[1]"), Ok(0.0));
+    }
+
+    #[test]
+    fn array_bounds_names() {
+        let ir = module_ir("let t[2]; t[0] = 1; t[1] = t[0]; { let t[2]; t[1] = 2; }");
+        // Shadowing arrays share their name
+        assert_eq!(ir.matches("c\"t\\00\"").count(), 1);
+    }
+
+    #[test]
+    fn array_unchecked() {
+        let options = CodegenOptions {
+            bounds_check: false,
+        };
+        assert_eq!(execute_with("let t[2]; t[1] = 3; t[1]", &options), Ok(3.0))
+    }
+
+    #[test]
+    fn pointer() {
+        assert_eq!(execute("let a = 1; let p = &a; *p = *p + 2; a"), Ok(3.0))
+    }
+
+    #[test]
+    fn pointer_to_element() {
+        assert_eq!(
+            execute("let t = [1, 2]; let p = &t[1]; *p = 5; t[1]"),
+            Ok(5.0)
+        )
+    }
+
+    #[test]
+    fn swap() {
+        assert_eq!(
+            execute(
+                "fn swap(a, b) { let t = *a; *a = *b; *b = t; return 0; }
+                 let x = 1.0; let y = 2.0; swap(&x, &y); x * 10 + y"
+            ),
+            Ok(21.0)
+        )
+    }
+
+    #[test]
+    fn deref_number() {
+        match execute("let a = 1; *a") {
+            Err(CodegenError::Rejected(errors)) => assert_eq!(
+                errors[0].message,
+                "mismatched types: expected pointer, found int"
+            ),
+            r => panic!("expected a type mismatch, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn ref_param() {
+        assert_eq!(
+            execute("fn inc(ref a) { a = a + 1; return a; } let x = 1.0; inc(x); inc(x); x"),
+            Ok(3.0)
+        )
+    }
+
+    #[test]
+    fn ref_array() {
+        assert_eq!(
+            execute(
+                "fn sum(ref t) { let s = 0; let i = 0; while i < len(t) { s = s + t[i]; i = i + 1; } return s; }
+                 global g = [1, 2]; let t = [1, 2, 3]; sum(t) * 10 + sum(g)"
+            ),
+            Ok(63.0)
+        )
+    }
+
+    #[test]
+    fn ref_not_variable() {
+        match execute("fn inc(ref a) { a = a + 1; return a; } inc(1)") {
+            Err(CodegenError::Unsupported { .. }) => (),
+            r => panic!("expected an unsupported reference, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn and_or() {
+        assert_eq!(execute("(1 && 2) + (0 || 0) + (0 || 3)"), Ok(2.0))
+    }
+
+    #[test]
+    fn short_circuit() {
+        assert_eq!(
+            execute(
+                "global c = 0; fn f() { c = c + 1; return 1; }
+                 let a = 0; (0 && f()) + (1 || f()) + (1 && (a = 2)) + c + a"
+            ),
+            Ok(4.0)
+        )
+    }
+
+    #[test]
+    fn pow() {
+        assert_eq!(execute("2 ^ 10 + 3 ^ 0"), Ok(1025.0))
+    }
+
+    #[test]
+    fn pow_right_assoc() {
+        assert_eq!(execute("2 ^ 3 ^ 2"), Ok(512.0))
+    }
+
+    #[test]
+    fn pow_intrinsic() {
+        assert_eq!(execute("let e = 0.5; 9 ^ e + 2 ^ 20"), Ok(1048579.0))
+    }
+
+    #[test]
+    fn case() {
+        assert_eq!(
+            execute(
+                "fn name(x) { let r = 0; case x { 1 => { r = 10; }, 2 => { r = 20; }, _ => { r = -1; } } return r; }
+                 name(1) + name(2.5) + name(7)"
+            ),
+            Ok(29.0)
+        )
+    }
+
+    #[test]
+    fn case_without_default() {
+        assert_eq!(
+            execute("let a = 5; case a - 5 { 0 => { a = 1; } } a"),
+            Ok(1.0)
+        )
+    }
+
+    #[test]
+    fn elif() {
+        assert_eq!(
+            execute(
+                "fn sign(x) { let r = 0; if x < 0 then { r = -1; } elif x == 0 then { r = 0; } else { r = 1; } return r; }
+                 sign(-5) * 100 + sign(0) * 10 + sign(3)"
+            ),
+            Ok(-99.0)
+        )
+    }
+
+    #[test]
+    fn elif_value() {
+        assert_eq!(
+            execute("let a = 2; if a == 1 then { a = 10; } elif a == 2 then { a = 20; } elif a == 3 then { a = 30; }"),
+            Ok(20.0)
         )
     }
+
+    #[test]
+    fn constant() {
+        assert_eq!(
+            execute("const N = 10; let t[N]; t[N - 1] = N ^ 2; t[9]"),
+            Ok(100.0)
+        )
+    }
+
+    #[test]
+    fn factorial() {
+        assert_eq!(execute("fn f(n) { return n!; } f(5) + 3!"), Ok(126.0))
+    }
 }
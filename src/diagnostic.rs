@@ -0,0 +1,106 @@
+use std::fmt;
+use std::string::String;
+
+/// Location of a piece of source code : byte offsets plus the (1-based)
+/// line and column of its first character.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    /// Span covering both `self` and the following `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+/// An error reported to the user, pointing at the offending source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub span: Span,
+    pub message: String,
+    pub expected: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(file: &str, span: Span, message: &str) -> Self {
+        Self {
+            file: String::from(file),
+            span,
+            message: String::from(message),
+            expected: vec![],
+        }
+    }
+
+    pub fn expecting(mut self, expected: Vec<String>) -> Self {
+        self.expected = expected;
+        self
+    }
+
+    /// Renders the diagnostic with the offending line and a caret under it
+    pub fn render(&self, source: &str) -> String {
+        let line = source
+            .lines()
+            .nth(self.span.line.saturating_sub(1))
+            .unwrap_or("");
+        let number = self.span.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        // Keep tabs so that the caret stays aligned with the source line
+        let indent: String = line
+            .chars()
+            .take(self.span.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = source
+            .get(self.span.start..self.span.end)
+            .and_then(|s| s.lines().next())
+            .map_or(0, |s| s.chars().count())
+            .max(1);
+
+        let mut out = format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter,
+            self.file,
+            self.span.line,
+            self.span.col,
+            gutter,
+            number,
+            line,
+            gutter,
+            indent,
+            "^".repeat(width),
+        );
+        if !self.expected.is_empty() {
+            out.push_str(&format!(
+                "\n{} = expected {}",
+                gutter,
+                self.expected.join(", ")
+            ));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.span.line, self.span.col, self.message
+        )?;
+        if !self.expected.is_empty() {
+            write!(f, " (expected {})", self.expected.join(", "))?;
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,11 @@
 // Declare the modules
 pub mod ast;
 pub mod codegen;
+pub mod diagnostic;
 pub mod parser;
+pub mod resolve;
+pub mod runtime;
+pub mod typeck;
 
 extern crate clap;
 extern crate inkwell;
@@ -12,13 +16,16 @@ extern crate pest_derive;
 extern crate lazy_static;
 
 use clap::Clap;
-use codegen::{create_jit_module, JitFunc};
+use codegen::{create_jit_module, run, CodegenError, CodegenOptions};
 use inkwell::context::Context;
-use inkwell::execution_engine::JitFunction;
-use inkwell::OptimizationLevel;
+use inkwell::module::Module;
+use parser::parse;
+use resolve::resolve;
 use std::fs;
 use std::io::{self, Write};
+use std::process;
 use std::string::String;
+use typeck::check;
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "Sheepsody")]
@@ -38,17 +45,57 @@ struct Compile {
     file: String,
     #[clap(short, long)]
     ir: Option<String>,
+    /// Do not check array indexes at runtime
+    #[clap(long)]
+    no_bounds_check: bool,
+    /// Print the IR of functions rejected by the LLVM verifier, which always runs
+    #[clap(long, alias = "dump-invalid-ir")]
+    verify_ir: bool,
 }
 
-fn compile_file(file: &str, ir: Option<String>) -> Result<f64, &str> {
+/// Parses & compiles `content`, errors are rendered against the source
+///
+/// With `dump_ir`, invalid IR is printed after its diagnostic
+fn build_module<'a>(
+    context: &'a Context,
+    file: &str,
+    content: &str,
+    options: &CodegenOptions,
+    dump_ir: bool,
+) -> Result<Module<'a>, String> {
+    parse(file, content)
+        .and_then(|nodes| resolve(file, nodes))
+        .and_then(|nodes| check(file, nodes))
+        .map_err(CodegenError::Rejected)
+        .and_then(|nodes| create_jit_module(context, &nodes, options))
+        .map_err(|e| render_errors(e, file, content, dump_ir))
+}
+
+/// Renders a compilation error against the source, with the rejected IR if `dump_ir`
+fn render_errors(e: CodegenError, file: &str, content: &str, dump_ir: bool) -> String {
+    let ir = match &e {
+        CodegenError::InvalidIr { ir, .. } if dump_ir => Some(ir.clone()),
+        _ => None,
+    };
+    let mut errors: Vec<String> = e
+        .into_diagnostics(file)
+        .iter()
+        .map(|d| d.render(content))
+        .collect();
+    errors.extend(ir);
+    errors.join("\n\n")
+}
+
+fn compile_file(
+    file: &str,
+    ir: Option<String>,
+    options: &CodegenOptions,
+    dump_ir: bool,
+) -> Result<f64, String> {
     match fs::read_to_string(file) {
         Ok(content) => {
             let context = Context::create();
-            let module = create_jit_module(&context, &content);
-
-            let execution_engine = module
-                .create_jit_execution_engine(OptimizationLevel::None)
-                .unwrap();
+            let module = build_module(&context, file, &content, options, dump_ir)?;
 
             if let Some(filename) = ir {
                 println!("LLVM IR Code:");
@@ -58,13 +105,9 @@ fn compile_file(file: &str, ir: Option<String>) -> Result<f64, &str> {
                 }
             }
 
-            unsafe {
-                let jit_function: JitFunction<JitFunc> =
-                    execution_engine.get_function("jit").unwrap();
-                Ok(jit_function.call())
-            }
+            run(&module).map_err(|e| e.to_string())
         }
-        _ => Err("Could not open file."),
+        _ => Err(String::from("Could not open file.")),
     }
 }
 
@@ -89,17 +132,27 @@ fn jit() -> Result<(), &'static str> {
         let content = history.clone() + &s;
 
         let context = Context::create();
-        let module = create_jit_module(&context, &content);
-
-        let execution_engine = module
-            .create_jit_execution_engine(OptimizationLevel::None)
-            .unwrap();
+        let module = match build_module(
+            &context,
+            "<stdin>",
+            &content,
+            &CodegenOptions::default(),
+            false,
+        ) {
+            Ok(module) => module,
+            Err(errors) => {
+                eprintln!("{}", errors);
+                continue;
+            }
+        };
 
-        let r;
-        unsafe {
-            let jit_function: JitFunction<JitFunc> = execution_engine.get_function("jit").unwrap();
-            r = jit_function.call();
-        }
+        let r = match run(&module) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
 
         if r.is_nan() {
             history.push_str(&s);
@@ -113,13 +166,57 @@ fn main() {
     let opts: Opts = Opts::parse();
 
     match opts.subcmd {
-        SubCommand::Comp(comp) => match compile_file(&comp.file, comp.ir) {
-            Ok(r) => println!("Got result : {}", r),
-            Err(s) => eprintln!("{}", s),
-        },
+        SubCommand::Comp(comp) => {
+            let options = CodegenOptions {
+                bounds_check: !comp.no_bounds_check,
+            };
+            match compile_file(&comp.file, comp.ir, &options, comp.verify_ir) {
+                Ok(r) => println!("Got result : {}", r),
+                // Runtime errors stop the command, not the library
+                Err(s) => {
+                    eprintln!("{}", s);
+                    process::exit(1);
+                }
+            }
+        }
         SubCommand::Jit => match jit() {
             Ok(_) => (),
             Err(s) => eprintln!("{}", s),
         },
     }
 }
+
+#[cfg(test)]
+mod cli {
+    use super::*;
+    use crate::diagnostic::Span;
+
+    #[test]
+    fn invalid_ir() {
+        let content = "let a = 1;\nfn f(x) { return x; }\nf(a)";
+        let error = || CodegenError::InvalidIr {
+            function: Some(String::from("f")),
+            message: String::from("Terminator found in the middle of a basic block!"),
+            ir: String::from("define double @f(double %x) {"),
+            span: Span {
+                start: 11,
+                end: 32,
+                line: 2,
+                col: 1,
+            },
+        };
+
+        // The diagnostic names the Gengo function and points at its definition
+        let errors = render_errors(error(), "test.gengo", content, false);
+        assert!(errors.starts_with(
+            "error: internal compiler error: invalid IR for function `f`: Terminator found"
+        ));
+        assert!(errors.contains("--> test.gengo:2:1"));
+        assert!(errors.contains("fn f(x) { return x; }"));
+        assert!(!errors.contains("define double @f"));
+
+        // `--verify-ir` prints the rejected IR after it
+        let errors = render_errors(error(), "test.gengo", content, true);
+        assert!(errors.ends_with("\n\ndefine double @f(double %x) {"));
+    }
+}
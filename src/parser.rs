@@ -1,8 +1,13 @@
 use super::ast::*;
+use super::diagnostic::{Diagnostic, Span};
+use super::runtime::factorial_value;
 
+use pest::error::{ErrorVariant, InputLocation, LineColLocation};
 use pest::iterators::*;
 use pest::prec_climber::*;
 use pest::Parser;
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 #[derive(Parser)]
 #[grammar = "genko.grammar"] // relative to project `src`
@@ -13,161 +18,706 @@ lazy_static! {
         use Assoc::*;
         use Rule::*;
 
+        // From the loosest to the tightest, unary operators bind before all of them
         PrecClimber::new(vec![
-            // Binary
-            Operator::new(add, Left) | Operator::new(sub, Left) ,
-            Operator::new(mul, Left) | Operator::new(div, Left),
-            Operator::new(pow, Right),
-            Operator::new(eq, Left)
-                | Operator::new(lt, Left)
+            Operator::new(or, Left),
+            Operator::new(and, Left),
+            Operator::new(eq, Left) | Operator::new(ne, Left),
+            Operator::new(lt, Left)
                 | Operator::new(le, Left)
                 | Operator::new(gt, Left)
-                | Operator::new(ge, Left)
-                | Operator::new(and, Left)
-                | Operator::new(or, Left),
-            // Unary
-            Operator::new(not, Left) | Operator::new(modulo, Left)
+                | Operator::new(ge, Left),
+            Operator::new(add, Left) | Operator::new(sub, Left),
+            Operator::new(mul, Left) | Operator::new(div, Left) | Operator::new(modulo, Left),
+            Operator::new(pow, Right),
         ])
     };
 }
 
-fn parse_pair(pair: Pair<Rule>) -> Node {
-    match pair.as_rule() {
-        Rule::num => Node::NumberExpr(pair.as_str().parse::<f64>().unwrap()),
-        Rule::ident => Node::IdentExpr(String::from(pair.as_str())),
-        Rule::bool => Node::BoolExpr(match pair.as_str() {
-            "true" => true,
-            "false" => false,
-            _ => unreachable!("Received bad boolean type {:?}", pair.as_str()),
-        }),
-        Rule::unaryexpr => {
-            let mut pair = pair.into_inner();
-            let rule = pair.next().unwrap().as_rule();
-            let operator = match rule {
-                Rule::sub => UnaryOp::Sub,
-                Rule::not => UnaryOp::Not,
-                _ => unimplemented!("Unary operator not {:?}", rule),
-            };
-            Node::UnaryExpr {
-                op: operator,
-                child: Box::new(parse_pairs(pair)),
-            }
-        }
-        // Predecence climbing
-        Rule::binaryexpr => parse_pairs(pair.into_inner()),
-        Rule::initexpr => {
-            let mut pair = pair.into_inner();
-            let ident = Box::new(Node::IdentExpr(String::from(pair.next().unwrap().as_str())));
-            let expr = Box::new(parse_pairs(pair));
-            Node::InitExpr { ident, expr }
-        }
-        Rule::globalexpr => {
-            let mut pair = pair.into_inner();
-            let ident = Box::new(Node::IdentExpr(String::from(pair.next().unwrap().as_str())));
-            let expr = Box::new(parse_pairs(pair));
-            Node::GlobalInitExpr { ident, expr }
+/// Turns the pest parse tree into `Node`s, reporting errors against `file`
+struct AstBuilder<'f> {
+    file: &'f str,
+    /// Symbolic constants declared so far, substituted in expressions
+    constants: RefCell<HashMap<String, Constant>>,
+}
+
+impl<'f> AstBuilder<'f> {
+    fn error(&self, pair: &Pair<Rule>, message: &str) -> Diagnostic {
+        Diagnostic::new(self.file, span_of(pair), message)
+    }
+
+    /// Next child of `pair`, the grammar guarantees it exists
+    fn next<'i>(
+        &self,
+        pair: &Pair<'i, Rule>,
+        inner: &mut Pairs<'i, Rule>,
+    ) -> Result<Pair<'i, Rule>, Diagnostic> {
+        inner
+            .next()
+            .ok_or_else(|| self.error(pair, &format!("malformed {:?}", pair.as_rule())))
+    }
+
+    fn ident<'i>(
+        &self,
+        pair: &Pair<'i, Rule>,
+        inner: &mut Pairs<'i, Rule>,
+    ) -> Result<Box<Node>, Diagnostic> {
+        let ident = self.next(pair, inner)?;
+        Ok(Box::new(Node::new(
+            NodeKind::IdentExpr(String::from(ident.as_str())),
+            span_of(&ident),
+        )))
+    }
+
+    /// Name of a new variable, which cannot hide a constant
+    fn declaration<'i>(
+        &self,
+        pair: &Pair<'i, Rule>,
+        inner: &mut Pairs<'i, Rule>,
+    ) -> Result<Box<Node>, Diagnostic> {
+        let ident = self.next(pair, inner)?;
+        let name = self.declared_name(&ident)?;
+        Ok(Box::new(Node::new(
+            NodeKind::IdentExpr(name),
+            span_of(&ident),
+        )))
+    }
+
+    /// Name of a new variable or parameter, which may not hide a constant
+    fn declared_name(&self, ident: &Pair<Rule>) -> Result<String, Diagnostic> {
+        let name = ident.as_str();
+        if self.constants.borrow().contains_key(name) {
+            let message = format!("`{}` is already declared as a constant", name);
+            return Err(self.error(ident, &message));
         }
-        Rule::assignexpr => {
-            let mut pair = pair.into_inner();
-            let ident = Box::new(Node::IdentExpr(String::from(pair.next().unwrap().as_str())));
-            let expr = Box::new(parse_pairs(pair));
-            Node::AssignExpr { ident, expr }
+        Ok(String::from(name))
+    }
+
+    /// Optional type annotation, the next child when it is a `vartype` or an
+    /// `arraytype`
+    fn annotation(&self, inner: &mut Pairs<Rule>) -> Option<Type> {
+        match inner.peek() {
+            Some(pair) if pair.as_rule() == Rule::vartype => {
+                inner.next();
+                Some(vartype(pair))
+            }
+            Some(pair) if pair.as_rule() == Rule::arraytype => {
+                inner.next();
+                let elem = pair.into_inner().next().map_or(Type::Float, vartype);
+                Some(Type::Array(Box::new(elem)))
+            }
+            _ => None,
         }
-        Rule::blockexpr => Node::BlockExpr(
-            pair.into_inner()
-                .into_iter()
-                .map(|p| parse_pair(p))
-                .collect(),
-        ),
-        Rule::funcexpr => {
-            let mut pair = pair.into_inner();
-            let ident = Box::new(Node::IdentExpr(String::from(pair.next().unwrap().as_str())));
-            let proto = pair.next().unwrap();
-            let args = proto
-                .into_inner()
-                .into_iter()
-                .map(|p| String::from(p.as_str()))
-                .collect();
-            let body = Box::new(parse_pair(pair.next().unwrap()));
-            Node::FuncExpr { ident, args, body }
+    }
+
+    /// Value of the constant named by `ident`
+    fn constant(&self, ident: &Pair<Rule>) -> Result<Constant, Diagnostic> {
+        match self.constants.borrow().get(ident.as_str()) {
+            Some(value) => Ok(*value),
+            None => Err(self.error(ident, &format!("unknown constant `{}`", ident.as_str()))),
         }
-        Rule::callexpr => {
-            let mut pairs: Vec<Node> = pair
-                .into_inner()
-                .into_iter()
-                .map(|e| parse_pair(e))
-                .collect();
-            let ident = Box::new(pairs.remove(0));
-            Node::CallExpr { ident, args: pairs }
+    }
+
+    /// Declared length of an array, a literal or a constant
+    fn size<'i>(
+        &self,
+        pair: &Pair<'i, Rule>,
+        inner: &mut Pairs<'i, Rule>,
+    ) -> Result<u32, Diagnostic> {
+        let size = self.next(pair, inner)?;
+        let value = match size.as_rule() {
+            Rule::ident => match self.constant(&size)? {
+                Constant::Int(value) => Some(value),
+                _ => None,
+            },
+            _ => size.as_str().parse::<i64>().ok(),
+        };
+        match value {
+            Some(value) if (1..=i64::from(u32::MAX)).contains(&value) => Ok(value as u32),
+            _ => Err(self.error(&size, "array size must be a positive integer")),
         }
-        Rule::condexpr => {
-            let mut pair = pair.into_inner();
-            let cond = parse_pair(pair.next().unwrap());
-            let cons = parse_pair(pair.next().unwrap());
-            let alter = match pair.next() {
-                Some(p) => Option::Some(Box::new(parse_pair(p))),
-                None => None,
-            };
-            Node::CondExpr {
-                cond: Box::new(cond),
-                cons: Box::new(cons),
-                alter,
+    }
+
+    /// Value of a case label, a literal or a constant
+    fn label(&self, label: &Pair<Rule>) -> Result<i64, Diagnostic> {
+        let value = match label.as_rule() {
+            Rule::ident => match self.constant(label)? {
+                Constant::Int(value) => Some(value),
+                _ => None,
+            },
+            _ => label.as_str().parse::<i64>().ok(),
+        };
+        value.ok_or_else(|| self.error(label, "case label must be an integer"))
+    }
+
+    fn parse_pair(&self, pair: Pair<Rule>) -> Result<Node, Diagnostic> {
+        let span = span_of(&pair);
+        let kind = match pair.as_rule() {
+            Rule::int => match pair.as_str().parse::<i64>() {
+                Ok(nb) => NodeKind::IntExpr(nb),
+                Err(_) => return Err(self.error(&pair, "invalid number literal")),
+            },
+            Rule::num => match pair.as_str().parse::<f64>() {
+                Ok(nb) => NodeKind::NumberExpr(nb),
+                Err(_) => return Err(self.error(&pair, "invalid number literal")),
+            },
+            Rule::ident => match self.constants.borrow().get(pair.as_str()) {
+                Some(value) => value.literal(),
+                None => NodeKind::IdentExpr(String::from(pair.as_str())),
+            },
+            Rule::bool => NodeKind::BoolExpr(match pair.as_str() {
+                "true" => true,
+                "false" => false,
+                _ => return Err(self.error(&pair, "invalid boolean literal")),
+            }),
+            Rule::unaryexpr => {
+                let mut inner = pair.clone().into_inner();
+                let op = self.next(&pair, &mut inner)?;
+                let operator = match op.as_rule() {
+                    Rule::sub => UnaryOp::Sub,
+                    Rule::not => UnaryOp::Not,
+                    Rule::address => UnaryOp::Address,
+                    Rule::deref => UnaryOp::Deref,
+                    _ => return Err(self.error(&op, "not a unary operator")),
+                };
+                NodeKind::UnaryExpr {
+                    op: operator,
+                    child: Box::new(self.parse_pairs(inner)?),
+                }
             }
-        }
-        Rule::whileexpr => {
-            let mut pair = pair.into_inner();
-            let cond = parse_pair(pair.next().unwrap());
-            let body = parse_pair(pair.next().unwrap());
-            Node::WhileExpr {
-                cond: Box::new(cond),
-                body: Box::new(body),
+            Rule::factexpr => {
+                let mut inner = pair.clone().into_inner();
+                let mut node = self.parse_pair(self.next(&pair, &mut inner)?)?;
+                for fact in inner {
+                    let span = node.span.to(span_of(&fact));
+                    node = Node::new(
+                        NodeKind::UnaryExpr {
+                            op: UnaryOp::Factorial,
+                            child: Box::new(node),
+                        },
+                        span,
+                    );
+                }
+                return Ok(node);
+            }
+            // Predecence climbing
+            Rule::binaryexpr => return self.parse_pairs(pair.into_inner()),
+            Rule::initexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ident = self.declaration(&pair, &mut inner)?;
+                let ty = self.annotation(&mut inner);
+                let expr = Box::new(self.parse_pairs(inner)?);
+                NodeKind::InitExpr { ident, ty, expr }
+            }
+            Rule::globalexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ident = self.declaration(&pair, &mut inner)?;
+                let ty = self.annotation(&mut inner);
+                let expr = Box::new(self.parse_pairs(inner)?);
+                NodeKind::GlobalInitExpr { ident, ty, expr }
+            }
+            Rule::arrayexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ident = self.declaration(&pair, &mut inner)?;
+                let size = self.size(&pair, &mut inner)?;
+                let ty = self.annotation(&mut inner);
+                NodeKind::ArrayInitExpr { ident, size, ty }
+            }
+            Rule::globalarrayexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ident = self.declaration(&pair, &mut inner)?;
+                let size = self.size(&pair, &mut inner)?;
+                let ty = self.annotation(&mut inner);
+                NodeKind::GlobalArrayInitExpr { ident, size, ty }
             }
+            Rule::constexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ident = self.declaration(&pair, &mut inner)?;
+                let expr = self.parse_pairs(inner)?;
+                let value = match fold(&expr) {
+                    Some(value) => value,
+                    None => {
+                        let (message, span) = division_error(&expr)
+                            .unwrap_or(("constant value must be known at compile time", expr.span));
+                        return Err(Diagnostic::new(self.file, span, message));
+                    }
+                };
+                if let NodeKind::IdentExpr(name) = &ident.kind {
+                    self.constants.borrow_mut().insert(name.clone(), value);
+                }
+                NodeKind::ConstExpr { ident, value }
+            }
+            Rule::arraylit => NodeKind::ArrayExpr(
+                pair.into_inner()
+                    .map(|p| self.parse_pair(p))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Rule::indexexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ident = self.ident(&pair, &mut inner)?;
+                let index = Box::new(self.parse_pairs(inner)?);
+                NodeKind::IndexExpr { ident, index }
+            }
+            Rule::assignexpr => {
+                let mut inner = pair.clone().into_inner();
+                // Either a variable, an array element or a dereference
+                let target = self.next(&pair, &mut inner)?;
+                if target.as_rule() == Rule::ident
+                    && self.constants.borrow().contains_key(target.as_str())
+                {
+                    let message = format!("cannot assign to constant `{}`", target.as_str());
+                    return Err(self.error(&target, &message));
+                }
+                let ident = Box::new(self.parse_pair(target)?);
+                let expr = Box::new(self.parse_pairs(inner)?);
+                NodeKind::AssignExpr { ident, expr }
+            }
+            // Constants declared in a block end with it
+            Rule::blockexpr => {
+                let tail = matches!(
+                    pair.clone().into_inner().last(),
+                    Some(last) if last.as_rule() == Rule::binaryexpr
+                );
+                let outer = self.constants.borrow().clone();
+                let nodes = pair
+                    .into_inner()
+                    .map(|p| self.parse_pair(p))
+                    .collect::<Result<_, _>>();
+                self.constants.replace(outer);
+                NodeKind::BlockExpr {
+                    nodes: nodes?,
+                    tail,
+                }
+            }
+            Rule::funcexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ident = self.ident(&pair, &mut inner)?;
+                let proto = self.next(&pair, &mut inner)?;
+                let args = proto
+                    .into_inner()
+                    .map(|param| {
+                        let mut inner = param.clone().into_inner();
+                        let name = self.next(&param, &mut inner)?;
+                        let (name, by_ref) = match name.as_rule() {
+                            Rule::refparam => {
+                                (self.next(&name, &mut name.clone().into_inner())?, true)
+                            }
+                            _ => (name, false),
+                        };
+                        let ty = self.annotation(&mut inner);
+                        if let (false, Some(Type::Array(_))) = (by_ref, &ty) {
+                            let message = "arrays are passed by reference, with `ref`";
+                            return Err(self.error(&param, message));
+                        }
+                        Ok(Param {
+                            name: self.declared_name(&name)?,
+                            by_ref,
+                            ty,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                let ret = self.annotation(&mut inner);
+                let body = Box::new(self.parse_pair(self.next(&pair, &mut inner)?)?);
+                NodeKind::FuncExpr {
+                    ident,
+                    args,
+                    ret,
+                    body,
+                }
+            }
+            Rule::callexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ident = self.ident(&pair, &mut inner)?;
+                let args = inner
+                    .map(|e| self.parse_pair(e))
+                    .collect::<Result<_, _>>()?;
+                NodeKind::CallExpr { ident, args }
+            }
+            Rule::condexpr => {
+                let mut inner = pair.clone().into_inner();
+                let cond = self.parse_pair(self.next(&pair, &mut inner)?)?;
+                let cons = self.parse_pair(self.next(&pair, &mut inner)?)?;
+
+                let mut elifs = vec![];
+                let mut alter = None;
+                for p in inner {
+                    match p.as_rule() {
+                        Rule::elifexpr => elifs.push(p),
+                        _ => alter = Some(Box::new(self.parse_pair(p)?)),
+                    }
+                }
+
+                // `elif`s are nested in the `else` of the previous condition
+                for elif in elifs.into_iter().rev() {
+                    let elif_span = span_of(&elif).to(span);
+                    let mut elif_inner = elif.clone().into_inner();
+                    let cond = self.parse_pair(self.next(&elif, &mut elif_inner)?)?;
+                    let cons = self.parse_pair(self.next(&elif, &mut elif_inner)?)?;
+                    alter = Some(Box::new(Node::new(
+                        NodeKind::CondExpr {
+                            cond: Box::new(cond),
+                            cons: Box::new(cons),
+                            alter,
+                        },
+                        elif_span,
+                    )));
+                }
+
+                NodeKind::CondExpr {
+                    cond: Box::new(cond),
+                    cons: Box::new(cons),
+                    alter,
+                }
+            }
+            Rule::caseexpr => {
+                let mut inner = pair.clone().into_inner();
+                let expr = Box::new(self.parse_pair(self.next(&pair, &mut inner)?)?);
+                let mut arms: Vec<(i64, Node)> = vec![];
+                let mut default = None;
+                for arm in inner {
+                    let mut arm_inner = arm.clone().into_inner();
+                    let label = self.next(&arm, &mut arm_inner)?;
+                    let body = self.parse_pair(self.next(&arm, &mut arm_inner)?)?;
+                    match label.as_rule() {
+                        Rule::wildcard if default.is_some() => {
+                            return Err(self.error(&label, "duplicate default case"))
+                        }
+                        Rule::wildcard => default = Some(Box::new(body)),
+                        _ => {
+                            let value = self.label(&label)?;
+                            if arms.iter().any(|(other, _)| *other == value) {
+                                return Err(self
+                                    .error(&label, &format!("duplicate case label `{}`", value)));
+                            }
+                            arms.push((value, body));
+                        }
+                    }
+                }
+                NodeKind::CaseExpr {
+                    expr,
+                    arms,
+                    default,
+                }
+            }
+            Rule::whileexpr => {
+                let mut inner = pair.clone().into_inner();
+                let cond = self.parse_pair(self.next(&pair, &mut inner)?)?;
+                let body = self.parse_pair(self.next(&pair, &mut inner)?)?;
+                NodeKind::WhileExpr {
+                    cond: Box::new(cond),
+                    body: Box::new(body),
+                }
+            }
+            Rule::forexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ident = self.declaration(&pair, &mut inner)?;
+                let start = Box::new(self.parse_pair(self.next(&pair, &mut inner)?)?);
+                let end = Box::new(self.parse_pair(self.next(&pair, &mut inner)?)?);
+                let mut rest = inner
+                    .map(|p| self.parse_pair(p).map(Box::new))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let body = match rest.pop() {
+                    Some(body) => body,
+                    None => return Err(self.error(&pair, "malformed forexpr")),
+                };
+                NodeKind::ForExpr {
+                    ident,
+                    start,
+                    end,
+                    step: rest.pop(),
+                    body,
+                }
+            }
+            Rule::returnexpr => {
+                let mut inner = pair.clone().into_inner();
+                let ret = Box::new(self.parse_pair(self.next(&pair, &mut inner)?)?);
+                NodeKind::ReturnExpr { ret }
+            }
+            Rule::breakexpr => NodeKind::BreakExpr,
+            Rule::continueexpr => NodeKind::ContinueExpr,
+            rule => return Err(self.error(&pair, &format!("unexpected {:?}", rule))),
+        };
+        Ok(Node::new(kind, span))
+    }
+
+    fn reduce(
+        &self,
+        lhs: Result<Node, Diagnostic>,
+        op: Pair<Rule>,
+        rhs: Result<Node, Diagnostic>,
+    ) -> Result<Node, Diagnostic> {
+        let operator = match op.as_rule() {
+            Rule::add => BinaryOp::Add,
+            Rule::sub => BinaryOp::Sub,
+            Rule::mul => BinaryOp::Mul,
+            Rule::div => BinaryOp::Div,
+            Rule::pow => BinaryOp::Pow,
+            Rule::eq => BinaryOp::Eq,
+            Rule::lt => BinaryOp::Lt,
+            Rule::gt => BinaryOp::Gt,
+            Rule::le => BinaryOp::Le,
+            Rule::ge => BinaryOp::Ge,
+            Rule::and => BinaryOp::And,
+            Rule::or => BinaryOp::Or,
+            Rule::ne => BinaryOp::Ne,
+            Rule::modulo => BinaryOp::Modulo,
+            _ => return Err(self.error(&op, "not a binary operator")),
+        };
+        let (lhs, rhs) = (lhs?, rhs?);
+        let span = lhs.span.to(rhs.span);
+        Ok(Node::new(
+            NodeKind::BinaryExpr {
+                op: operator,
+                rhs: Box::new(rhs),
+                lhs: Box::new(lhs),
+            },
+            span,
+        ))
+    }
+
+    fn parse_pairs(&self, pairs: Pairs<Rule>) -> Result<Node, Diagnostic> {
+        PREC_CLIMBER.climb(
+            pairs,
+            |p| self.parse_pair(p),
+            |lhs, op, rhs| self.reduce(lhs, op, rhs),
+        )
+    }
+}
+
+/// Value of a constant expression, `None` when it depends on the program
+///
+/// Types follow the codegen : integers stay integers unless mixed with floats,
+/// booleans count as integers in arithmetic
+pub fn fold(node: &Node) -> Option<Constant> {
+    match &node.kind {
+        NodeKind::IntExpr(nb) => Some(Constant::Int(*nb)),
+        NodeKind::NumberExpr(nb) => Some(Constant::Float(*nb)),
+        NodeKind::BoolExpr(b) => Some(Constant::Bool(*b)),
+        NodeKind::CastExpr { ty, expr } => fold(expr)?.cast(ty),
+        NodeKind::UnaryExpr { op, child } => match (op, fold(child)?) {
+            (UnaryOp::Sub, Constant::Float(nb)) => Some(Constant::Float(-nb)),
+            (UnaryOp::Sub, child) => Some(Constant::Int(arithmetic(child).wrapping_neg())),
+            (UnaryOp::Not, child) => Some(Constant::Bool(child.to_f64() == 0.0)),
+            (UnaryOp::Factorial, child) => Some(Constant::Float(factorial_value(child.to_f64()))),
+            (UnaryOp::Address, _) | (UnaryOp::Deref, _) => None,
+        },
+        NodeKind::BinaryExpr { op, lhs, rhs } => match (fold(lhs)?, fold(rhs)?) {
+            (Constant::Float(lhs), rhs) => Some(fold_float(*op, lhs, rhs.to_f64())),
+            (lhs, Constant::Float(rhs)) => Some(fold_float(*op, lhs.to_f64(), rhs)),
+            (lhs, rhs) => fold_int(*op, arithmetic(lhs), arithmetic(rhs)),
+        },
+        _ => None,
+    }
+}
+
+/// Integer division of constants which cannot be folded, and where it is
+fn division_error(node: &Node) -> Option<(&'static str, Span)> {
+    if let Some(error) = node.children().into_iter().find_map(division_error) {
+        return Some(error);
+    }
+    match &node.kind {
+        NodeKind::BinaryExpr {
+            op: BinaryOp::Div,
+            lhs,
+            rhs,
         }
-        Rule::returnexpr => {
-            let ret = Box::new(parse_pair(pair.into_inner().next().unwrap()));
-            Node::ReturnExpr { ret }
+        | NodeKind::BinaryExpr {
+            op: BinaryOp::Modulo,
+            lhs,
+            rhs,
+        } => match (fold(lhs)?, fold(rhs)?) {
+            (Constant::Float(_), _) | (_, Constant::Float(_)) => None,
+            (lhs, rhs) => match (arithmetic(lhs), arithmetic(rhs)) {
+                (_, 0) => Some(("division by zero", node.span)),
+                (i64::MIN, -1) => Some(("integer overflow in division", node.span)),
+                _ => None,
+            },
+        },
+        _ => None,
+    }
+}
+
+/// Integer value of a constant which is not a float
+fn arithmetic(value: Constant) -> i64 {
+    match value {
+        Constant::Int(nb) => nb,
+        Constant::Bool(b) => i64::from(b),
+        Constant::Float(nb) => nb as i64,
+    }
+}
+
+/// Integer operations wrap, undefined divisions are reported by `division_error`
+fn fold_int(op: BinaryOp, lhs: i64, rhs: i64) -> Option<Constant> {
+    Some(match op {
+        BinaryOp::Add => Constant::Int(lhs.wrapping_add(rhs)),
+        BinaryOp::Sub => Constant::Int(lhs.wrapping_sub(rhs)),
+        BinaryOp::Mul => Constant::Int(lhs.wrapping_mul(rhs)),
+        BinaryOp::Div => Constant::Int(lhs.checked_div(rhs)?),
+        BinaryOp::Modulo => Constant::Int(lhs.checked_rem(rhs)?),
+        BinaryOp::Pow if (0..=i64::from(u32::MAX)).contains(&rhs) => {
+            Constant::Int(lhs.wrapping_pow(rhs as u32))
         }
-        _ => unreachable!(),
+        BinaryOp::Eq => Constant::Bool(lhs == rhs),
+        BinaryOp::Ne => Constant::Bool(lhs != rhs),
+        BinaryOp::Lt => Constant::Bool(lhs < rhs),
+        BinaryOp::Le => Constant::Bool(lhs <= rhs),
+        BinaryOp::Gt => Constant::Bool(lhs > rhs),
+        BinaryOp::Ge => Constant::Bool(lhs >= rhs),
+        BinaryOp::And => Constant::Bool(lhs != 0 && rhs != 0),
+        BinaryOp::Or => Constant::Bool(lhs != 0 || rhs != 0),
+        BinaryOp::Pow => Constant::Float((lhs as f64).powf(rhs as f64)),
+    })
+}
+
+fn fold_float(op: BinaryOp, lhs: f64, rhs: f64) -> Constant {
+    match op {
+        BinaryOp::Add => Constant::Float(lhs + rhs),
+        BinaryOp::Sub => Constant::Float(lhs - rhs),
+        BinaryOp::Mul => Constant::Float(lhs * rhs),
+        BinaryOp::Div => Constant::Float(lhs / rhs),
+        BinaryOp::Pow => Constant::Float(lhs.powf(rhs)),
+        BinaryOp::Modulo => Constant::Float(lhs % rhs),
+        BinaryOp::Eq => Constant::Bool(lhs == rhs),
+        BinaryOp::Ne => Constant::Bool(lhs != rhs),
+        BinaryOp::Lt => Constant::Bool(lhs < rhs),
+        BinaryOp::Le => Constant::Bool(lhs <= rhs),
+        BinaryOp::Gt => Constant::Bool(lhs > rhs),
+        BinaryOp::Ge => Constant::Bool(lhs >= rhs),
+        BinaryOp::And => Constant::Bool(lhs != 0.0 && rhs != 0.0),
+        BinaryOp::Or => Constant::Bool(lhs != 0.0 || rhs != 0.0),
     }
 }
 
-fn reduce(lhs: Node, op: Pair<Rule>, rhs: Node) -> Node {
-    let operator = match op.as_rule() {
-        Rule::add => BinaryOp::Add,
-        Rule::sub => BinaryOp::Sub,
-        Rule::mul => BinaryOp::Mul,
-        Rule::div => BinaryOp::Div,
-        Rule::pow => BinaryOp::Pow,
-        Rule::eq => BinaryOp::Eq,
-        Rule::lt => BinaryOp::Lt,
-        Rule::gt => BinaryOp::Gt,
-        Rule::le => BinaryOp::Le,
-        Rule::ge => BinaryOp::Ge,
-        Rule::and => BinaryOp::And,
-        Rule::or => BinaryOp::Or,
-        Rule::ne => BinaryOp::Ne,
-        Rule::modulo => BinaryOp::Modulo,
-        _ => unimplemented!("Operator {:?} not supported as binary", op),
-    };
-    Node::BinaryExpr {
-        op: operator,
-        rhs: Box::new(rhs),
-        lhs: Box::new(lhs),
+fn span_of(pair: &Pair<Rule>) -> Span {
+    let span = pair.as_span();
+    let (line, col) = span.start_pos().line_col();
+    Span {
+        start: span.start(),
+        end: span.end(),
+        line,
+        col,
     }
 }
 
-fn parse_pairs(pairs: Pairs<Rule>) -> Node {
-    PREC_CLIMBER.climb(pairs, parse_pair, reduce)
+/// Type written in an annotation, each leading `*` adds a pointer
+fn vartype(pair: Pair<Rule>) -> Type {
+    let mut depth = 0;
+    let mut ty = Type::Float;
+    for p in pair.into_inner() {
+        match (p.as_rule(), p.as_str()) {
+            (Rule::deref, _) => depth += 1,
+            (_, "int") => ty = Type::Int,
+            (_, "bool") => ty = Type::Bool,
+            _ => ty = Type::Float,
+        }
+    }
+    (0..depth).fold(ty, |ty, _| Type::Pointer(Box::new(ty)))
+}
+
+/// Human readable name of what the grammar was expecting
+fn describe(rule: &Rule) -> String {
+    String::from(match rule {
+        Rule::EOI => "end of input",
+        Rule::num | Rule::int | Rule::uint => "number",
+        Rule::bool => "boolean",
+        Rule::ident => "identifier",
+        Rule::add => "`+`",
+        Rule::sub => "`-`",
+        Rule::mul => "`*`",
+        Rule::div => "`/`",
+        Rule::pow => "`^`",
+        Rule::eq => "`==`",
+        Rule::ne => "`!=`",
+        Rule::le => "`<=`",
+        Rule::ge => "`>=`",
+        Rule::lt => "`<`",
+        Rule::gt => "`>`",
+        Rule::not | Rule::fact => "`!`",
+        Rule::and => "`&&`",
+        Rule::or => "`||`",
+        Rule::modulo => "`%`",
+        Rule::address => "`&`",
+        Rule::deref => "`*`",
+        Rule::protoexpr => "parameter list",
+        Rule::param | Rule::refparam => "parameter",
+        Rule::vartype | Rule::basetype | Rule::arraytype => "type",
+        Rule::caselabel => "case label",
+        Rule::wildcard => "`_`",
+        Rule::casearm => "case arm",
+        Rule::indexexpr => "array element",
+        Rule::arraylit => "array literal",
+        Rule::blockexpr => "block",
+        Rule::callexpr | Rule::assignexpr | Rule::binaryexpr | Rule::unaryexpr | Rule::factexpr => {
+            "expression"
+        }
+        _ => "statement",
+    })
+}
+
+fn syntax_error(file: &str, source: &str, error: pest::error::Error<Rule>) -> Diagnostic {
+    let (start, end) = match error.location {
+        InputLocation::Pos(pos) => (pos, pos),
+        InputLocation::Span(span) => span,
+    };
+    let (line, col) = match error.line_col {
+        LineColLocation::Pos(pos) => pos,
+        LineColLocation::Span(pos, _) => pos,
+    };
+
+    // Point at the whole token found instead of its first character
+    let rest = &source[start..];
+    let token: String = match rest.chars().next() {
+        Some(c) if c.is_alphanumeric() || c == '_' => rest
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '.')
+            .collect(),
+        Some(c) => c.to_string(),
+        None => String::new(),
+    };
+    let span = Span {
+        start,
+        end: end.max(start + token.len()),
+        line,
+        col,
+    };
+
+    match error.variant {
+        ErrorVariant::ParsingError { positives, .. } => {
+            let message = match token.as_str() {
+                "" => String::from("unexpected end of input"),
+                token => format!("unexpected `{}`", token),
+            };
+            let mut expected: Vec<String> = positives.iter().map(describe).collect();
+            expected.sort();
+            expected.dedup();
+            Diagnostic::new(file, span, &message).expecting(expected)
+        }
+        ErrorVariant::CustomError { message } => Diagnostic::new(file, span, &message),
+    }
 }
 
-pub fn parse(string: &str) -> Vec<Node> {
-    let pairs = GenkoParser::parse(Rule::program, string).unwrap_or_else(|e| panic!("{}", e));
+pub fn parse(file: &str, string: &str) -> Result<Vec<Node>, Vec<Diagnostic>> {
+    let pairs = GenkoParser::parse(Rule::program, string)
+        .map_err(|e| vec![syntax_error(file, string, e)])?;
+
+    let builder = AstBuilder {
+        file,
+        constants: RefCell::new(HashMap::new()),
+    };
+    let mut nodes = vec![];
+    let mut errors = vec![];
+    for pair in pairs.filter(|p| !p.as_str().is_empty()) {
+        match builder.parse_pair(pair) {
+            Ok(node) => nodes.push(node),
+            Err(e) => errors.push(e),
+        }
+    }
 
-    pairs
-        .into_iter()
-        .filter(|p| !p.as_str().is_empty())
-        .map(|p| parse_pair(p))
-        .collect()
+    if errors.is_empty() {
+        Ok(nodes)
+    } else {
+        Err(errors)
+    }
 }
 
 #[cfg(test)]
@@ -175,19 +725,45 @@ mod parsing {
     use super::*;
 
     fn parse_single(string: &str) -> Node {
-        parse(string).remove(0)
+        parse("test", string).unwrap().remove(0)
+    }
+
+    fn node(kind: NodeKind) -> Node {
+        Node::from(kind)
+    }
+
+    fn boxed(kind: NodeKind) -> Box<Node> {
+        Box::new(node(kind))
+    }
+
+    fn ident(name: &str) -> Node {
+        node(NodeKind::IdentExpr(String::from(name)))
+    }
+
+    fn binop(op: BinaryOp, lhs: Node, rhs: Node) -> Node {
+        node(NodeKind::BinaryExpr {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        })
     }
 
     #[test]
     fn number() {
-        assert_eq!(parse_single("1"), Node::NumberExpr(1.0));
+        assert_eq!(parse_single("1"), node(NodeKind::IntExpr(1)));
+    }
+
+    #[test]
+    fn float() {
+        assert_eq!(parse_single("1.5"), node(NodeKind::NumberExpr(1.5)));
+        assert_eq!(parse_single("2e3"), node(NodeKind::NumberExpr(2000.0)));
     }
 
     #[test]
     fn comments() {
         assert_eq!(
             parse_single("/* Test */ # TEST \n 1"),
-            Node::NumberExpr(1.0)
+            node(NodeKind::IntExpr(1))
         );
     }
 
@@ -195,26 +771,144 @@ mod parsing {
     fn binary() {
         assert_eq!(
             parse_single("1+2"),
-            Node::BinaryExpr {
+            node(NodeKind::BinaryExpr {
                 op: BinaryOp::Add,
-                lhs: Box::new(Node::NumberExpr(1.0)),
-                rhs: Box::new(Node::NumberExpr(2.0))
-            }
+                lhs: boxed(NodeKind::IntExpr(1)),
+                rhs: boxed(NodeKind::IntExpr(2))
+            })
+        )
+    }
+    #[test]
+    fn precedence_modulo() {
+        assert_eq!(
+            parse_single("a + b % c"),
+            binop(
+                BinaryOp::Add,
+                ident("a"),
+                binop(BinaryOp::Modulo, ident("b"), ident("c"))
+            )
         )
     }
+
+    #[test]
+    fn precedence_logical() {
+        assert_eq!(
+            parse_single("x < 1 || y > 2 && z"),
+            binop(
+                BinaryOp::Or,
+                binop(BinaryOp::Lt, ident("x"), node(NodeKind::IntExpr(1))),
+                binop(
+                    BinaryOp::And,
+                    binop(BinaryOp::Gt, ident("y"), node(NodeKind::IntExpr(2))),
+                    ident("z")
+                )
+            )
+        )
+    }
+
+    #[test]
+    fn precedence_equality() {
+        assert_eq!(
+            parse_single("a == b < c != d"),
+            binop(
+                BinaryOp::Ne,
+                binop(
+                    BinaryOp::Eq,
+                    ident("a"),
+                    binop(BinaryOp::Lt, ident("b"), ident("c"))
+                ),
+                ident("d")
+            )
+        )
+    }
+
+    #[test]
+    fn precedence_arithmetic() {
+        assert_eq!(
+            parse_single("a - b * c ^ d ^ e - f"),
+            binop(
+                BinaryOp::Sub,
+                binop(
+                    BinaryOp::Sub,
+                    ident("a"),
+                    binop(
+                        BinaryOp::Mul,
+                        ident("b"),
+                        binop(
+                            BinaryOp::Pow,
+                            ident("c"),
+                            binop(BinaryOp::Pow, ident("d"), ident("e"))
+                        )
+                    )
+                ),
+                ident("f")
+            )
+        )
+    }
+
+    #[test]
+    fn precedence_unary() {
+        assert_eq!(
+            parse_single("!a && -b ^ 2"),
+            binop(
+                BinaryOp::And,
+                node(NodeKind::UnaryExpr {
+                    op: UnaryOp::Not,
+                    child: Box::new(ident("a"))
+                }),
+                binop(
+                    BinaryOp::Pow,
+                    node(NodeKind::UnaryExpr {
+                        op: UnaryOp::Sub,
+                        child: Box::new(ident("b"))
+                    }),
+                    node(NodeKind::IntExpr(2))
+                )
+            )
+        )
+    }
+
+    #[test]
+    fn factorial() {
+        assert_eq!(
+            parse_single("!n! != 1"),
+            binop(
+                BinaryOp::Ne,
+                node(NodeKind::UnaryExpr {
+                    op: UnaryOp::Not,
+                    child: boxed(NodeKind::UnaryExpr {
+                        op: UnaryOp::Factorial,
+                        child: Box::new(ident("n"))
+                    })
+                }),
+                node(NodeKind::IntExpr(1))
+            )
+        )
+    }
+
+    #[test]
+    fn factorial_constant() {
+        let nodes = parse("test", "const N = 3!!; N").unwrap();
+        assert_eq!(nodes[1], node(NodeKind::NumberExpr(720.0)));
+    }
+
     #[test]
     fn identifier() {
-        assert_eq!(parse_single("x"), Node::IdentExpr(String::from("x")))
+        assert_eq!(
+            parse_single("x"),
+            node(NodeKind::IdentExpr(String::from("x")))
+        )
     }
 
     #[test]
     fn init_var() {
         assert_eq!(
             parse_single("let a = 1; a"),
-            Node::InitExpr {
-                ident: Box::new(Node::IdentExpr(String::from("a"))),
-                expr: Box::new(Node::NumberExpr(1.0))
-            }
+            node(NodeKind::InitExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("a"))),
+                ty: None,
+                expr: boxed(NodeKind::IntExpr(1))
+            })
         )
     }
 
@@ -222,10 +916,11 @@ mod parsing {
     fn global_var() {
         assert_eq!(
             parse_single("global a = 1; a"),
-            Node::GlobalInitExpr {
-                ident: Box::new(Node::IdentExpr(String::from("a"))),
-                expr: Box::new(Node::NumberExpr(1.0))
-            }
+            node(NodeKind::GlobalInitExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("a"))),
+                ty: None,
+                expr: boxed(NodeKind::IntExpr(1))
+            })
         )
     }
 
@@ -233,10 +928,10 @@ mod parsing {
     fn assignement() {
         assert_eq!(
             parse_single("a = 1; a"),
-            Node::AssignExpr {
-                ident: Box::new(Node::IdentExpr(String::from("a"))),
-                expr: Box::new(Node::NumberExpr(1.0))
-            }
+            node(NodeKind::AssignExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("a"))),
+                expr: boxed(NodeKind::IntExpr(1))
+            })
         )
     }
 
@@ -244,24 +939,55 @@ mod parsing {
     fn block() {
         assert_eq!(
             parse_single("{a = 1;} 1"),
-            Node::BlockExpr(vec![Node::AssignExpr {
-                ident: Box::new(Node::IdentExpr(String::from("a"))),
-                expr: Box::new(Node::NumberExpr(1.0))
-            }])
+            node(NodeKind::BlockExpr {
+                nodes: vec![node(NodeKind::AssignExpr {
+                    ident: boxed(NodeKind::IdentExpr(String::from("a"))),
+                    expr: boxed(NodeKind::IntExpr(1))
+                })],
+                tail: false,
+            })
         );
     }
 
+    #[test]
+    fn block_value() {
+        assert_eq!(
+            parse_single("{a = 1; a + 1} 1"),
+            node(NodeKind::BlockExpr {
+                nodes: vec![
+                    node(NodeKind::AssignExpr {
+                        ident: boxed(NodeKind::IdentExpr(String::from("a"))),
+                        expr: boxed(NodeKind::IntExpr(1))
+                    }),
+                    node(NodeKind::BinaryExpr {
+                        op: BinaryOp::Add,
+                        lhs: boxed(NodeKind::IdentExpr(String::from("a"))),
+                        rhs: boxed(NodeKind::IntExpr(1))
+                    })
+                ],
+                tail: true,
+            })
+        );
+    }
+
+    #[test]
+    fn block_assignment_value() {
+        let errors = parse("test", "fn f() -> int { x = 1 }").unwrap_err();
+        assert_eq!((errors[0].span.line, errors[0].span.col), (1, 23));
+        assert!(parse("test", "fn f() -> int { (x = 1) }").is_ok());
+    }
+
     #[test]
     fn assignement_chaining() {
         assert_eq!(
             parse_single("b = a = 1"),
-            Node::AssignExpr {
-                ident: Box::new(Node::IdentExpr(String::from("b"))),
-                expr: Box::new(Node::AssignExpr {
-                    ident: Box::new(Node::IdentExpr(String::from("a"))),
-                    expr: Box::new(Node::NumberExpr(1.0))
+            node(NodeKind::AssignExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("b"))),
+                expr: boxed(NodeKind::AssignExpr {
+                    ident: boxed(NodeKind::IdentExpr(String::from("a"))),
+                    expr: boxed(NodeKind::IntExpr(1))
                 })
-            }
+            })
         )
     }
 
@@ -269,11 +995,15 @@ mod parsing {
     fn func_declaration_empty() {
         assert_eq!(
             parse_single("fn cat() {} 1"),
-            Node::FuncExpr {
-                ident: Box::new(Node::IdentExpr(String::from("cat"))),
+            node(NodeKind::FuncExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("cat"))),
                 args: vec![],
-                body: Box::new(Node::BlockExpr(vec![]))
-            }
+                ret: None,
+                body: boxed(NodeKind::BlockExpr {
+                    nodes: vec![],
+                    tail: false,
+                })
+            })
         )
     }
 
@@ -281,17 +1011,105 @@ mod parsing {
     fn func_declaration() {
         assert_eq!(
             parse_single("fn cat(a, b) { return 6+4; } 6"),
-            Node::FuncExpr {
-                ident: Box::new(Node::IdentExpr(String::from("cat"))),
-                args: vec![String::from("a"), String::from("b")],
-                body: Box::new(Node::BlockExpr(vec![Node::ReturnExpr {
-                    ret: Box::new(Node::BinaryExpr {
-                        op: BinaryOp::Add,
-                        lhs: Box::new(Node::NumberExpr(6.0)),
-                        rhs: Box::new(Node::NumberExpr(4.0))
-                    })
-                }])),
+            node(NodeKind::FuncExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("cat"))),
+                args: vec![
+                    Param {
+                        name: String::from("a"),
+                        by_ref: false,
+                        ty: None
+                    },
+                    Param {
+                        name: String::from("b"),
+                        by_ref: false,
+                        ty: None
+                    }
+                ],
+                ret: None,
+                body: boxed(NodeKind::BlockExpr {
+                    nodes: vec![node(NodeKind::ReturnExpr {
+                        ret: boxed(NodeKind::BinaryExpr {
+                            op: BinaryOp::Add,
+                            lhs: boxed(NodeKind::IntExpr(6)),
+                            rhs: boxed(NodeKind::IntExpr(4))
+                        })
+                    })],
+                    tail: false,
+                }),
+            })
+        )
+    }
+
+    #[test]
+    fn func_ref_param() {
+        assert_eq!(
+            parse_single("fn inc(ref a, reference) {} 1"),
+            node(NodeKind::FuncExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("inc"))),
+                args: vec![
+                    Param {
+                        name: String::from("a"),
+                        by_ref: true,
+                        ty: None
+                    },
+                    Param {
+                        name: String::from("reference"),
+                        by_ref: false,
+                        ty: None
+                    }
+                ],
+                ret: None,
+                body: boxed(NodeKind::BlockExpr {
+                    nodes: vec![],
+                    tail: false,
+                })
+            })
+        )
+    }
+
+    #[test]
+    fn annotations() {
+        assert_eq!(
+            parse_single("fn f(a: int, ref p: *bool) -> float {} 1"),
+            node(NodeKind::FuncExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("f"))),
+                args: vec![
+                    Param {
+                        name: String::from("a"),
+                        by_ref: false,
+                        ty: Some(Type::Int)
+                    },
+                    Param {
+                        name: String::from("p"),
+                        by_ref: true,
+                        ty: Some(Type::Pointer(Box::new(Type::Bool)))
+                    }
+                ],
+                ret: Some(Type::Float),
+                body: boxed(NodeKind::BlockExpr {
+                    nodes: vec![],
+                    tail: false,
+                })
+            })
+        );
+        match parse_single("fn f(ref t: [int]) {} 1").kind {
+            NodeKind::FuncExpr { args, .. } => {
+                assert_eq!(args[0].ty, Some(Type::Array(Box::new(Type::Int))))
             }
+            kind => panic!("expected a function, got {:?}", kind),
+        }
+        let errors = parse("test", "fn f(t: [int]) {}").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "arrays are passed by reference, with `ref`"
+        );
+        assert_eq!(
+            parse_single("let x: float = 0; x"),
+            node(NodeKind::InitExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("x"))),
+                ty: Some(Type::Float),
+                expr: boxed(NodeKind::IntExpr(0))
+            })
         )
     }
 
@@ -299,10 +1117,10 @@ mod parsing {
     fn call_empty() {
         assert_eq!(
             parse_single("ze()"),
-            Node::CallExpr {
-                ident: Box::new(Node::IdentExpr(String::from("ze"))),
+            node(NodeKind::CallExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("ze"))),
                 args: vec![]
-            }
+            })
         )
     }
 
@@ -310,42 +1128,46 @@ mod parsing {
     fn call() {
         assert_eq!(
             parse_single("yz(1+3, cd)"),
-            Node::CallExpr {
-                ident: Box::new(Node::IdentExpr(String::from("yz"))),
+            node(NodeKind::CallExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("yz"))),
                 args: vec![
-                    Node::BinaryExpr {
-                        lhs: Box::new(Node::NumberExpr(1.0)),
+                    node(NodeKind::BinaryExpr {
+                        lhs: boxed(NodeKind::IntExpr(1)),
                         op: BinaryOp::Add,
-                        rhs: Box::new(Node::NumberExpr(3.0)),
-                    },
-                    Node::IdentExpr(String::from("cd")),
+                        rhs: boxed(NodeKind::IntExpr(3)),
+                    }),
+                    node(NodeKind::IdentExpr(String::from("cd"))),
                 ]
-            }
+            })
         )
     }
 
     #[test]
     fn bool_false() {
-        assert_eq!(parse_single("false"), Node::BoolExpr(false),)
+        assert_eq!(parse_single("false"), node(NodeKind::BoolExpr(false)),)
     }
 
     #[test]
     fn bool_true() {
-        assert_eq!(parse_single("true"), Node::BoolExpr(true),)
+        assert_eq!(parse_single("true"), node(NodeKind::BoolExpr(true)),)
     }
 
     #[test]
     fn cond_if() {
         assert_eq!(
             parse_single("if true then {let a = 1;} c"),
-            Node::CondExpr {
-                cond: Box::new(Node::BoolExpr(true)),
-                cons: Box::new(Node::BlockExpr(vec![Node::InitExpr {
-                    ident: Box::new(Node::IdentExpr(String::from("a"))),
-                    expr: Box::new(Node::NumberExpr(1.0))
-                },])),
+            node(NodeKind::CondExpr {
+                cond: boxed(NodeKind::BoolExpr(true)),
+                cons: boxed(NodeKind::BlockExpr {
+                    nodes: vec![node(NodeKind::InitExpr {
+                        ident: boxed(NodeKind::IdentExpr(String::from("a"))),
+                        ty: None,
+                        expr: boxed(NodeKind::IntExpr(1))
+                    }),],
+                    tail: false,
+                }),
                 alter: Option::None,
-            }
+            })
         )
     }
 
@@ -353,21 +1175,55 @@ mod parsing {
     fn cond_if_else() {
         assert_eq!(
             parse_single("if a == 0 then {let a = 1;} else {let b = 2;} c"),
-            Node::CondExpr {
-                cond: Box::new(Node::BinaryExpr {
+            node(NodeKind::CondExpr {
+                cond: boxed(NodeKind::BinaryExpr {
                     op: BinaryOp::Eq,
-                    lhs: Box::new(Node::IdentExpr(String::from("a"))),
-                    rhs: Box::new(Node::NumberExpr(0.0)),
+                    lhs: boxed(NodeKind::IdentExpr(String::from("a"))),
+                    rhs: boxed(NodeKind::IntExpr(0)),
                 }),
-                cons: Box::new(Node::BlockExpr(vec![Node::InitExpr {
-                    ident: Box::new(Node::IdentExpr(String::from("a"))),
-                    expr: Box::new(Node::NumberExpr(1.0))
-                },])),
-                alter: Some(Box::new(Node::BlockExpr(vec![Node::InitExpr {
-                    ident: Box::new(Node::IdentExpr(String::from("b"))),
-                    expr: Box::new(Node::NumberExpr(2.0))
-                },]))),
-            }
+                cons: boxed(NodeKind::BlockExpr {
+                    nodes: vec![node(NodeKind::InitExpr {
+                        ident: boxed(NodeKind::IdentExpr(String::from("a"))),
+                        ty: None,
+                        expr: boxed(NodeKind::IntExpr(1))
+                    }),],
+                    tail: false,
+                }),
+                alter: Some(boxed(NodeKind::BlockExpr {
+                    nodes: vec![node(NodeKind::InitExpr {
+                        ident: boxed(NodeKind::IdentExpr(String::from("b"))),
+                        ty: None,
+                        expr: boxed(NodeKind::IntExpr(2))
+                    }),],
+                    tail: false,
+                })),
+            })
+        )
+    }
+
+    #[test]
+    fn cond_elif() {
+        let block = || {
+            boxed(NodeKind::BlockExpr {
+                nodes: vec![],
+                tail: false,
+            })
+        };
+        assert_eq!(
+            parse_single("if a then {} elif b then {} elif c then {} else {} 1"),
+            node(NodeKind::CondExpr {
+                cond: Box::new(ident("a")),
+                cons: block(),
+                alter: Some(boxed(NodeKind::CondExpr {
+                    cond: Box::new(ident("b")),
+                    cons: block(),
+                    alter: Some(boxed(NodeKind::CondExpr {
+                        cond: Box::new(ident("c")),
+                        cons: block(),
+                        alter: Some(block()),
+                    })),
+                })),
+            })
         )
     }
 
@@ -375,13 +1231,376 @@ mod parsing {
     fn cond_while() {
         assert_eq!(
             parse_single("while true {let a=1;} c"),
-            Node::WhileExpr {
-                cond: Box::new(Node::BoolExpr(true)),
-                body: Box::new(Node::BlockExpr(vec![Node::InitExpr {
-                    ident: Box::new(Node::IdentExpr(String::from("a"))),
-                    expr: Box::new(Node::NumberExpr(1.0))
-                },])),
-            }
+            node(NodeKind::WhileExpr {
+                cond: boxed(NodeKind::BoolExpr(true)),
+                body: boxed(NodeKind::BlockExpr {
+                    nodes: vec![node(NodeKind::InitExpr {
+                        ident: boxed(NodeKind::IdentExpr(String::from("a"))),
+                        ty: None,
+                        expr: boxed(NodeKind::IntExpr(1))
+                    }),],
+                    tail: false,
+                }),
+            })
+        )
+    }
+
+    #[test]
+    fn break_continue() {
+        assert_eq!(
+            parse_single("while true {continue; break;} c"),
+            node(NodeKind::WhileExpr {
+                cond: boxed(NodeKind::BoolExpr(true)),
+                body: boxed(NodeKind::BlockExpr {
+                    nodes: vec![node(NodeKind::ContinueExpr), node(NodeKind::BreakExpr)],
+                    tail: false,
+                }),
+            })
+        );
+        assert!(parse("test", "let breaks = 1;").is_ok());
+    }
+
+    #[test]
+    fn for_range() {
+        assert_eq!(
+            parse_single("for i in 0..n step 2 {} c"),
+            node(NodeKind::ForExpr {
+                ident: Box::new(ident("i")),
+                start: boxed(NodeKind::IntExpr(0)),
+                end: Box::new(ident("n")),
+                step: Some(boxed(NodeKind::IntExpr(2))),
+                body: boxed(NodeKind::BlockExpr {
+                    nodes: vec![],
+                    tail: false,
+                }),
+            })
+        );
+        match parse_single("for i in 1..2 {} c").kind {
+            NodeKind::ForExpr { step, .. } => assert_eq!(step, None),
+            kind => panic!("expected a for loop, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn case() {
+        assert_eq!(
+            parse_single("case x { 1 => {}, -2 => { a = 1; }, _ => {} } 1"),
+            node(NodeKind::CaseExpr {
+                expr: Box::new(ident("x")),
+                arms: vec![
+                    (
+                        1,
+                        node(NodeKind::BlockExpr {
+                            nodes: vec![],
+                            tail: false,
+                        })
+                    ),
+                    (
+                        -2,
+                        node(NodeKind::BlockExpr {
+                            nodes: vec![node(NodeKind::AssignExpr {
+                                ident: Box::new(ident("a")),
+                                expr: boxed(NodeKind::IntExpr(1))
+                            })],
+                            tail: false,
+                        })
+                    ),
+                ],
+                default: Some(boxed(NodeKind::BlockExpr {
+                    nodes: vec![],
+                    tail: false,
+                })),
+            })
         )
     }
+
+    #[test]
+    fn case_duplicate_label() {
+        let errors = parse("test", "case x {\n 1 => {},\n 1 => {}\n}").unwrap_err();
+        assert_eq!(errors[0].message, "duplicate case label `1`");
+        assert_eq!((errors[0].span.line, errors[0].span.col), (3, 2));
+    }
+
+    #[test]
+    fn constant() {
+        let nodes = parse("test", "const N = 2 * 5; const M = N + 1;\nN * M").unwrap();
+        assert_eq!(
+            nodes[1],
+            node(NodeKind::ConstExpr {
+                ident: Box::new(ident("M")),
+                value: Constant::Int(11)
+            })
+        );
+        assert_eq!(
+            nodes[2],
+            binop(
+                BinaryOp::Mul,
+                node(NodeKind::IntExpr(10)),
+                node(NodeKind::IntExpr(11))
+            )
+        );
+    }
+
+    #[test]
+    fn constant_types() {
+        let nodes = parse("test", "const H = 7 / 2; const F = 7 / 2.0; H + F").unwrap();
+        assert_eq!(
+            nodes[2],
+            binop(
+                BinaryOp::Add,
+                node(NodeKind::IntExpr(3)),
+                node(NodeKind::NumberExpr(3.5))
+            )
+        );
+    }
+
+    #[test]
+    fn constant_size_and_label() {
+        let nodes = parse("test", "const N = 4; let t[N]; case x { N => {} }").unwrap();
+        assert_eq!(
+            nodes[1],
+            node(NodeKind::ArrayInitExpr {
+                ident: Box::new(ident("t")),
+                size: 4,
+                ty: None
+            })
+        );
+        assert_eq!(
+            nodes[2],
+            node(NodeKind::CaseExpr {
+                expr: Box::new(ident("x")),
+                arms: vec![(
+                    4,
+                    node(NodeKind::BlockExpr {
+                        nodes: vec![],
+                        tail: false,
+                    })
+                )],
+                default: None
+            })
+        );
+    }
+
+    #[test]
+    fn constant_errors() {
+        for (source, message) in &[
+            ("const N = 1; N = 2;", "cannot assign to constant `N`"),
+            (
+                "const N = 1; let N = 2;",
+                "`N` is already declared as a constant",
+            ),
+            (
+                "let a = 1; const N = a;",
+                "constant value must be known at compile time",
+            ),
+            (
+                "const N = 0.5; let t[N];",
+                "array size must be a positive integer",
+            ),
+            ("let t[N];", "unknown constant `N`"),
+            (
+                "const N = 3; fn f(N) { return N; }",
+                "`N` is already declared as a constant",
+            ),
+            (
+                "const N = 3; fn f(ref N) { return N; }",
+                "`N` is already declared as a constant",
+            ),
+            ("{ const N = 1; } let t[N];", "unknown constant `N`"),
+            ("const N = 1 + 4 / (2 - 2);", "division by zero"),
+            ("const N = 5 % 0;", "division by zero"),
+        ] {
+            let errors = parse("test", source).unwrap_err();
+            assert_eq!(errors[0].message, *message);
+        }
+        assert!(parse("test", "{ const N = 1; } const N = 2;").is_ok());
+    }
+
+    #[test]
+    fn syntax_error() {
+        let errors = parse("test.gengo", "let a = 1;\nlet b = ;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, "test.gengo");
+        assert_eq!((errors[0].span.line, errors[0].span.col), (2, 9));
+        assert_eq!(errors[0].message, "unexpected `;`");
+        assert!(errors[0].expected.contains(&String::from("expression")));
+    }
+
+    #[test]
+    fn assignment_target_error() {
+        for source in &[
+            "let x = 1; -x = 3",
+            "let x = 1; &x = 3",
+            "let x = 1; !x = 3",
+        ] {
+            let errors = parse("test.gengo", source).unwrap_err();
+            assert_eq!(errors[0].message, "unexpected `=`");
+        }
+    }
+
+    #[test]
+    fn syntax_error_render() {
+        let source = "fn f(a) {\n  return a +;\n}";
+        let errors = parse("test.gengo", source).unwrap_err();
+        assert_eq!(
+            errors[0].render(source),
+            "error: unexpected `;`\n --> test.gengo:2:13\n  |\n2 |   return a +;\n  |             ^\n  = expected boolean, expression, identifier, number"
+        );
+    }
+
+    #[test]
+    fn array_declaration() {
+        assert_eq!(
+            parse_single("let t[10]; 1"),
+            node(NodeKind::ArrayInitExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("t"))),
+                size: 10,
+                ty: None
+            })
+        );
+        assert_eq!(
+            parse_single("let t[10]: int; 1"),
+            node(NodeKind::ArrayInitExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("t"))),
+                size: 10,
+                ty: Some(Type::Int)
+            })
+        )
+    }
+
+    #[test]
+    fn array_literal() {
+        assert_eq!(
+            parse_single("let t = [1, 2]; 1"),
+            node(NodeKind::InitExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("t"))),
+                ty: None,
+                expr: boxed(NodeKind::ArrayExpr(vec![
+                    node(NodeKind::IntExpr(1)),
+                    node(NodeKind::IntExpr(2))
+                ]))
+            })
+        )
+    }
+
+    #[test]
+    fn global_array() {
+        assert_eq!(
+            parse_single("global t[100]; 1"),
+            node(NodeKind::GlobalArrayInitExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("t"))),
+                size: 100,
+                ty: None
+            })
+        )
+    }
+
+    #[test]
+    fn array_empty() {
+        let errors = parse("test", "let t[0];").unwrap_err();
+        assert_eq!(errors[0].message, "array size must be a positive integer");
+    }
+
+    #[test]
+    fn array_index() {
+        assert_eq!(
+            parse_single("t[i+1]"),
+            node(NodeKind::IndexExpr {
+                ident: boxed(NodeKind::IdentExpr(String::from("t"))),
+                index: boxed(NodeKind::BinaryExpr {
+                    op: BinaryOp::Add,
+                    lhs: boxed(NodeKind::IdentExpr(String::from("i"))),
+                    rhs: boxed(NodeKind::IntExpr(1))
+                })
+            })
+        )
+    }
+
+    #[test]
+    fn array_assignement() {
+        assert_eq!(
+            parse_single("t[0] = t[1]"),
+            node(NodeKind::AssignExpr {
+                ident: boxed(NodeKind::IndexExpr {
+                    ident: boxed(NodeKind::IdentExpr(String::from("t"))),
+                    index: boxed(NodeKind::IntExpr(0))
+                }),
+                expr: boxed(NodeKind::IndexExpr {
+                    ident: boxed(NodeKind::IdentExpr(String::from("t"))),
+                    index: boxed(NodeKind::IntExpr(1))
+                })
+            })
+        )
+    }
+
+    #[test]
+    fn address_of() {
+        assert_eq!(
+            parse_single("&a"),
+            node(NodeKind::UnaryExpr {
+                op: UnaryOp::Address,
+                child: boxed(NodeKind::IdentExpr(String::from("a")))
+            })
+        )
+    }
+
+    #[test]
+    fn deref_assignement() {
+        assert_eq!(
+            parse_single("*p = *q + 1"),
+            node(NodeKind::AssignExpr {
+                ident: boxed(NodeKind::UnaryExpr {
+                    op: UnaryOp::Deref,
+                    child: boxed(NodeKind::IdentExpr(String::from("p")))
+                }),
+                expr: boxed(NodeKind::BinaryExpr {
+                    op: BinaryOp::Add,
+                    lhs: boxed(NodeKind::UnaryExpr {
+                        op: UnaryOp::Deref,
+                        child: boxed(NodeKind::IdentExpr(String::from("q")))
+                    }),
+                    rhs: boxed(NodeKind::IntExpr(1))
+                })
+            })
+        )
+    }
+
+    #[test]
+    fn examples() {
+        for source in &[
+            include_str!("../examples/fact.gengo"),
+            include_str!("../examples/euclide.gengo"),
+            include_str!("../examples/eratosthene.gengo"),
+        ] {
+            assert!(parse("example", source).is_ok());
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let nodes = parse("test", "let a = 1;\n  a + 2").unwrap();
+        assert_eq!(
+            nodes[0].span,
+            Span {
+                start: 0,
+                end: 10,
+                line: 1,
+                col: 1
+            }
+        );
+        assert_eq!(
+            nodes[1].span,
+            Span {
+                start: 13,
+                end: 18,
+                line: 2,
+                col: 3
+            }
+        );
+        if let NodeKind::BinaryExpr { rhs, .. } = &nodes[1].kind {
+            assert_eq!((rhs.span.line, rhs.span.col), (2, 7));
+        } else {
+            panic!("expected a binary expression");
+        }
+    }
 }
@@ -0,0 +1,445 @@
+use super::ast::{Node, NodeKind, Param};
+use super::diagnostic::{Diagnostic, Span};
+use std::collections::{HashMap, HashSet};
+
+/// Variables of a block, or the parameters of a function
+struct Scope {
+    /// Declared names and the unique names they were given
+    names: HashMap<String, String>,
+    /// Names declared further down the block
+    pending: HashSet<String>,
+    /// Depth of the enclosing function, 0 for the top-level code
+    function: usize,
+}
+
+/// Binds each use of a variable to its declaration
+///
+/// Declarations are renamed so that names are unique in the program, shadowing
+/// ones get a `'` suffix which identifiers cannot contain. Later passes then
+/// need no scopes of their own.
+struct Resolver<'f> {
+    file: &'f str,
+    scopes: Vec<Scope>,
+    /// Depth of the function being resolved
+    function: usize,
+    /// Number of declarations of each name so far
+    counts: HashMap<String, usize>,
+    /// Loops around the node being resolved, within its function
+    loops: usize,
+    /// Unique names of the variables of `for` loops, which cannot be assigned
+    loop_variables: HashSet<String>,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'f> Resolver<'f> {
+    fn error(&mut self, span: Span, message: &str) {
+        self.errors.push(Diagnostic::new(self.file, span, message));
+    }
+
+    /// Opens the scope of a block, knowing what `nodes` will declare
+    fn push(&mut self, nodes: &[Node]) {
+        let pending = nodes
+            .iter()
+            .filter_map(|node| match &node.kind {
+                NodeKind::InitExpr { ident, .. }
+                | NodeKind::GlobalInitExpr { ident, .. }
+                | NodeKind::ArrayInitExpr { ident, .. }
+                | NodeKind::GlobalArrayInitExpr { ident, .. } => match &ident.kind {
+                    NodeKind::IdentExpr(name) => Some(name.clone()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        self.scopes.push(Scope {
+            names: HashMap::new(),
+            pending,
+            function: self.function,
+        });
+    }
+
+    /// Gives a unique name to a declaration of `name` in the innermost scope
+    fn declare_name(&mut self, name: &str, span: Span) -> String {
+        let scope = self.scopes.last_mut().unwrap();
+        if let Some(unique) = scope.names.get(name) {
+            let unique = unique.clone();
+            self.error(
+                span,
+                &format!("`{}` is already declared in this scope", name),
+            );
+            return unique;
+        }
+        scope.pending.remove(name);
+
+        let count = self.counts.entry(String::from(name)).or_insert(0);
+        let unique = match *count {
+            0 => String::from(name),
+            n => format!("{}'{}", name, n),
+        };
+        *count += 1;
+        scope.names.insert(String::from(name), unique.clone());
+        unique
+    }
+
+    fn declare(&mut self, ident: Box<Node>) -> Box<Node> {
+        match ident.kind {
+            NodeKind::IdentExpr(name) => {
+                let unique = self.declare_name(&name, ident.span);
+                Box::new(Node::new(NodeKind::IdentExpr(unique), ident.span))
+            }
+            _ => ident,
+        }
+    }
+
+    /// Unique name of the variable `name` used at `span`
+    ///
+    /// Functions see their own scopes and the top-level ones
+    fn lookup(&mut self, name: String, span: Span, assign: bool) -> String {
+        let visible = |scope: &&Scope| scope.function == 0 || scope.function == self.function;
+        let scopes = || self.scopes.iter().rev().filter(visible);
+
+        if let Some(unique) = scopes().find_map(|scope| scope.names.get(&name)) {
+            let unique = unique.clone();
+            if assign && self.loop_variables.contains(&unique) {
+                self.error(span, &format!("cannot assign to loop variable `{}`", name));
+            }
+            return unique;
+        }
+        let message = if scopes().any(|scope| scope.pending.contains(&name)) {
+            format!("`{}` is used before its declaration", name)
+        } else if assign {
+            format!("cannot assign to undeclared variable `{}`", name)
+        } else {
+            format!("undefined variable `{}`", name)
+        };
+        self.error(span, &message);
+        name
+    }
+
+    fn variable(&mut self, ident: Node, assign: bool) -> Box<Node> {
+        match ident.kind {
+            NodeKind::IdentExpr(name) => {
+                let unique = self.lookup(name, ident.span, assign);
+                Box::new(Node::new(NodeKind::IdentExpr(unique), ident.span))
+            }
+            kind => Box::new(self.resolve(Node::new(kind, ident.span))),
+        }
+    }
+
+    fn boxed(&mut self, node: Node) -> Box<Node> {
+        Box::new(self.resolve(node))
+    }
+
+    fn resolve(&mut self, node: Node) -> Node {
+        let span = node.span;
+        let kind = match node.kind {
+            NodeKind::IdentExpr(name) => NodeKind::IdentExpr(self.lookup(name, span, false)),
+            NodeKind::UnaryExpr { op, child } => NodeKind::UnaryExpr {
+                op,
+                child: self.boxed(*child),
+            },
+            NodeKind::BinaryExpr { op, lhs, rhs } => NodeKind::BinaryExpr {
+                op,
+                lhs: self.boxed(*lhs),
+                rhs: self.boxed(*rhs),
+            },
+            NodeKind::CastExpr { ty, expr } => NodeKind::CastExpr {
+                ty,
+                expr: self.boxed(*expr),
+            },
+            NodeKind::IndexExpr { ident, index } => NodeKind::IndexExpr {
+                ident: self.variable(*ident, false),
+                index: self.boxed(*index),
+            },
+            NodeKind::ArrayExpr(values) => {
+                NodeKind::ArrayExpr(values.into_iter().map(|v| self.resolve(v)).collect())
+            }
+
+            // Initializers are resolved first, `let a = a + 1;` uses the outer `a`
+            NodeKind::InitExpr { ident, ty, expr } => {
+                let expr = self.boxed(*expr);
+                let ident = self.declare(ident);
+                NodeKind::InitExpr { ident, ty, expr }
+            }
+            NodeKind::GlobalInitExpr { ident, ty, expr } => {
+                let expr = self.boxed(*expr);
+                let ident = self.declare(ident);
+                NodeKind::GlobalInitExpr { ident, ty, expr }
+            }
+            NodeKind::ArrayInitExpr { ident, size, ty } => NodeKind::ArrayInitExpr {
+                ident: self.declare(ident),
+                size,
+                ty,
+            },
+            NodeKind::GlobalArrayInitExpr { ident, size, ty } => NodeKind::GlobalArrayInitExpr {
+                ident: self.declare(ident),
+                size,
+                ty,
+            },
+            NodeKind::AssignExpr { ident, expr } => NodeKind::AssignExpr {
+                ident: self.variable(*ident, true),
+                expr: self.boxed(*expr),
+            },
+
+            NodeKind::BlockExpr { nodes, tail } => {
+                self.push(&nodes);
+                let nodes = nodes.into_iter().map(|node| self.resolve(node)).collect();
+                self.scopes.pop();
+                NodeKind::BlockExpr { nodes, tail }
+            }
+            // Parameters have their own scope, which the body may shadow
+            NodeKind::FuncExpr {
+                ident,
+                args,
+                ret,
+                body,
+            } => {
+                // Loops do not extend into functions
+                let loops = std::mem::replace(&mut self.loops, 0);
+                self.function += 1;
+                self.push(&[]);
+                let args = args
+                    .into_iter()
+                    .map(|arg| Param {
+                        name: self.declare_name(&arg.name, ident.span),
+                        ..arg
+                    })
+                    .collect();
+                let body = self.boxed(*body);
+                self.scopes.pop();
+                self.function -= 1;
+                self.loops = loops;
+                NodeKind::FuncExpr {
+                    ident,
+                    args,
+                    ret,
+                    body,
+                }
+            }
+            NodeKind::CallExpr { ident, args } => NodeKind::CallExpr {
+                ident,
+                args: args.into_iter().map(|arg| self.resolve(arg)).collect(),
+            },
+            NodeKind::ReturnExpr { ret } => NodeKind::ReturnExpr {
+                ret: self.boxed(*ret),
+            },
+
+            NodeKind::CondExpr { cond, cons, alter } => NodeKind::CondExpr {
+                cond: self.boxed(*cond),
+                cons: self.boxed(*cons),
+                alter: alter.map(|alter| self.boxed(*alter)),
+            },
+            NodeKind::CaseExpr {
+                expr,
+                arms,
+                default,
+            } => NodeKind::CaseExpr {
+                expr: self.boxed(*expr),
+                arms: arms
+                    .into_iter()
+                    .map(|(label, body)| (label, self.resolve(body)))
+                    .collect(),
+                default: default.map(|default| self.boxed(*default)),
+            },
+            NodeKind::WhileExpr { cond, body } => {
+                let cond = self.boxed(*cond);
+                self.loops += 1;
+                let body = self.boxed(*body);
+                self.loops -= 1;
+                NodeKind::WhileExpr { cond, body }
+            }
+            // The loop variable has its own scope, around the one of the body
+            NodeKind::ForExpr {
+                ident,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                let start = self.boxed(*start);
+                let end = self.boxed(*end);
+                let step = step.map(|step| self.boxed(*step));
+                self.push(&[]);
+                let ident = self.declare(ident);
+                if let NodeKind::IdentExpr(unique) = &ident.kind {
+                    self.loop_variables.insert(unique.clone());
+                }
+                self.loops += 1;
+                let body = self.boxed(*body);
+                self.loops -= 1;
+                self.scopes.pop();
+                NodeKind::ForExpr {
+                    ident,
+                    start,
+                    end,
+                    step,
+                    body,
+                }
+            }
+            kind @ NodeKind::BreakExpr | kind @ NodeKind::ContinueExpr => {
+                if self.loops == 0 {
+                    let keyword = match kind {
+                        NodeKind::BreakExpr => "break",
+                        _ => "continue",
+                    };
+                    self.error(span, &format!("`{}` outside of a loop", keyword));
+                }
+                kind
+            }
+
+            kind @ NodeKind::IntExpr(_)
+            | kind @ NodeKind::NumberExpr(_)
+            | kind @ NodeKind::BoolExpr(_)
+            | kind @ NodeKind::ConstExpr { .. } => kind,
+        };
+        Node::new(kind, span)
+    }
+}
+
+/// Name of a variable in the source, the unique name without its suffix
+pub fn source_name(unique: &str) -> &str {
+    unique.split('\'').next().unwrap_or(unique)
+}
+
+/// Resolves the variables of a parsed program, returning it with unique names
+pub fn resolve(file: &str, nodes: Vec<Node>) -> Result<Vec<Node>, Vec<Diagnostic>> {
+    let mut resolver = Resolver {
+        file,
+        scopes: vec![],
+        function: 0,
+        counts: HashMap::new(),
+        loops: 0,
+        loop_variables: HashSet::new(),
+        errors: vec![],
+    };
+    resolver.push(&nodes);
+    let nodes = nodes
+        .into_iter()
+        .map(|node| resolver.resolve(node))
+        .collect();
+
+    if resolver.errors.is_empty() {
+        Ok(nodes)
+    } else {
+        Err(resolver.errors)
+    }
+}
+
+#[cfg(test)]
+mod resolving {
+    use super::*;
+    use crate::parser::parse;
+
+    fn resolve_str(string: &str) -> Result<Vec<Node>, Vec<Diagnostic>> {
+        resolve("test", parse("test", string).unwrap())
+    }
+
+    fn ident(name: &str) -> Box<Node> {
+        Box::new(Node::from(NodeKind::IdentExpr(String::from(name))))
+    }
+
+    #[test]
+    fn shadowing() {
+        let nodes = resolve_str("let a = 1; { let a = a; a = 2; } a").unwrap();
+        assert_eq!(
+            nodes[1],
+            Node::from(NodeKind::BlockExpr {
+                nodes: vec![
+                    Node::from(NodeKind::InitExpr {
+                        ident: ident("a'1"),
+                        ty: None,
+                        expr: ident("a")
+                    }),
+                    Node::from(NodeKind::AssignExpr {
+                        ident: ident("a'1"),
+                        expr: Box::new(Node::from(NodeKind::IntExpr(2)))
+                    })
+                ],
+                tail: false,
+            })
+        );
+        assert_eq!(nodes[2], *ident("a"));
+    }
+
+    #[test]
+    fn function_scope() {
+        let nodes = resolve_str("let a = 1; fn f(a) { return a; } fn g() { return a; }").unwrap();
+        match (&nodes[1].kind, &nodes[2].kind) {
+            (NodeKind::FuncExpr { args, .. }, NodeKind::FuncExpr { body, .. }) => {
+                assert_eq!(args[0].name, "a'1");
+                assert_eq!(
+                    **body,
+                    Node::from(NodeKind::BlockExpr {
+                        nodes: vec![Node::from(NodeKind::ReturnExpr { ret: ident("a") })],
+                        tail: false,
+                    })
+                );
+            }
+            _ => panic!("expected functions"),
+        }
+    }
+
+    #[test]
+    fn loop_variable() {
+        let nodes = resolve_str("let i = 5; for i in 0..i { let i = i; } i").unwrap();
+        match &nodes[1].kind {
+            NodeKind::ForExpr {
+                ident: var,
+                end,
+                body,
+                ..
+            } => {
+                assert_eq!((var, end), (&ident("i'1"), &ident("i")));
+                assert_eq!(
+                    **body,
+                    Node::from(NodeKind::BlockExpr {
+                        nodes: vec![Node::from(NodeKind::InitExpr {
+                            ident: ident("i'2"),
+                            ty: None,
+                            expr: ident("i'1")
+                        })],
+                        tail: false,
+                    })
+                );
+            }
+            _ => panic!("expected a for loop"),
+        }
+        assert_eq!(nodes[2], *ident("i"));
+        assert_eq!(source_name("i'2"), "i");
+        assert_eq!(source_name("i"), "i");
+    }
+
+    #[test]
+    fn errors() {
+        let messages = |string| -> Vec<(String, usize, usize)> {
+            resolve_str(string)
+                .unwrap_err()
+                .into_iter()
+                .map(|d| (d.message, d.span.line, d.span.col))
+                .collect()
+        };
+        assert_eq!(
+            messages("let a = 1;\nlet a = 2;"),
+            vec![(String::from("`a` is already declared in this scope"), 2, 5)]
+        );
+        assert_eq!(
+            messages("fn f() { return b; }\nlet b = 1;"),
+            vec![(String::from("`b` is used before its declaration"), 1, 17)]
+        );
+        assert_eq!(
+            messages("{ let c = 1; }\nc"),
+            vec![(String::from("undefined variable `c`"), 2, 1)]
+        );
+        assert_eq!(
+            messages("for i in 0..3 {\n  let j = i; j = 1; i = 2;\n}"),
+            vec![(String::from("cannot assign to loop variable `i`"), 2, 21)]
+        );
+        assert_eq!(
+            messages("while 1 { fn f() { break; } }\ncontinue;"),
+            vec![
+                (String::from("`break` outside of a loop"), 1, 20),
+                (String::from("`continue` outside of a loop"), 2, 1)
+            ]
+        );
+    }
+}
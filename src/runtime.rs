@@ -0,0 +1,139 @@
+//! Functions called by the generated code, mapped into the JIT by `link`
+//!
+//! Runtime errors are recorded for `take_error` and raise the `gengo_failed`
+//! flag of the module, the generated code then returns up to the entry point
+
+use inkwell::context::Context;
+use inkwell::execution_engine::ExecutionEngine;
+use inkwell::module::Module;
+use inkwell::values::{FunctionValue, PointerValue};
+use inkwell::AddressSpace;
+use std::cell::RefCell;
+use std::f64::{INFINITY, NAN};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+/// Function wrapping the top-level code, called by the JIT
+pub const ENTRY: &str = "jit";
+
+const BOUNDS_ERROR: &str = "gengo_bounds_error";
+const DIVISION_ERROR: &str = "gengo_division_error";
+const FACTORIAL: &str = "gengo_factorial";
+const FAILED: &str = "gengo_failed";
+
+thread_local! {
+    /// First runtime error of the program running on this thread
+    static ERROR: RefCell<Option<String>> = RefCell::new(None);
+}
+
+fn report(message: String) {
+    ERROR.with(|error| {
+        error.borrow_mut().get_or_insert(message);
+    });
+}
+
+/// Runtime error of the last program run on this thread, if it stopped on one
+pub fn take_error() -> Option<String> {
+    ERROR.with(|error| error.borrow_mut().take())
+}
+
+/// Declares the `gengo_failed` flag, raised by the code after a runtime error
+pub fn failed<'ctx>(context: &'ctx Context, module: &Module<'ctx>) -> PointerValue<'ctx> {
+    let global = module.get_global(FAILED).unwrap_or_else(|| {
+        let bool_type = context.bool_type();
+        let global = module.add_global(bool_type, None, FAILED);
+        global.set_initializer(&bool_type.const_zero());
+        global
+    });
+    global.as_pointer_value()
+}
+
+/// Whether `name` is the entry point or a runtime function, which Gengo
+/// functions may not be named like
+pub fn is_reserved(name: &str) -> bool {
+    name == ENTRY || name.starts_with("gengo_")
+}
+
+/// Reports an out of bounds array access
+extern "C" fn gengo_bounds_error(name: *const c_char, index: i64, len: i64, line: i64) {
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    report(format!(
+        "line {}: index {} is out of bounds for array `{}` of length {}",
+        line, index, name, len
+    ));
+}
+
+/// Declares `gengo_bounds_error(name, index, len, line)` in the module
+pub fn bounds_error<'ctx>(context: &'ctx Context, module: &Module<'ctx>) -> FunctionValue<'ctx> {
+    module.get_function(BOUNDS_ERROR).unwrap_or_else(|| {
+        let i64_type = context.i64_type();
+        let name_type = context.i8_type().ptr_type(AddressSpace::Generic);
+        let fn_type = context.void_type().fn_type(
+            &[
+                name_type.into(),
+                i64_type.into(),
+                i64_type.into(),
+                i64_type.into(),
+            ],
+            false,
+        );
+        module.add_function(BOUNDS_ERROR, fn_type, None)
+    })
+}
+
+/// Reports an integer division by zero, or which overflows
+extern "C" fn gengo_division_error(divisor: i64, line: i64) {
+    report(match divisor {
+        0 => format!("line {}: division by zero", line),
+        _ => format!("line {}: integer overflow in division", line),
+    });
+}
+
+/// Declares `gengo_division_error(divisor, line)` in the module
+pub fn division_error<'ctx>(context: &'ctx Context, module: &Module<'ctx>) -> FunctionValue<'ctx> {
+    module.get_function(DIVISION_ERROR).unwrap_or_else(|| {
+        let i64_type = context.i64_type();
+        let fn_type = context
+            .void_type()
+            .fn_type(&[i64_type.into(), i64_type.into()], false);
+        module.add_function(DIVISION_ERROR, fn_type, None)
+    })
+}
+
+/// `n!`, NAN unless `n` is a natural number
+pub fn factorial_value(n: f64) -> f64 {
+    if n < 0.0 || n.fract() != 0.0 {
+        return NAN;
+    }
+    // 171! does not fit in a f64
+    if n > 170.0 {
+        return INFINITY;
+    }
+    (1..=n as u32).map(f64::from).product()
+}
+
+extern "C" fn gengo_factorial(n: f64) -> f64 {
+    factorial_value(n)
+}
+
+/// Declares `gengo_factorial(n)` in the module
+pub fn factorial<'ctx>(context: &'ctx Context, module: &Module<'ctx>) -> FunctionValue<'ctx> {
+    module.get_function(FACTORIAL).unwrap_or_else(|| {
+        let f64_type = context.f64_type();
+        let fn_type = f64_type.fn_type(&[f64_type.into()], false);
+        module.add_function(FACTORIAL, fn_type, None)
+    })
+}
+
+/// Maps the runtime functions used by `module` into the execution engine
+pub fn link<'ctx>(engine: &ExecutionEngine<'ctx>, module: &Module<'ctx>) {
+    if let Some(function) = module.get_function(BOUNDS_ERROR) {
+        engine.add_global_mapping(&function, gengo_bounds_error as *const () as usize);
+    }
+    if let Some(function) = module.get_function(DIVISION_ERROR) {
+        engine.add_global_mapping(&function, gengo_division_error as *const () as usize);
+    }
+    if let Some(function) = module.get_function(FACTORIAL) {
+        engine.add_global_mapping(&function, gengo_factorial as *const () as usize);
+    }
+}
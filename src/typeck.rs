@@ -0,0 +1,889 @@
+use super::ast::{BinaryOp, Constant, Node, NodeKind, Type, UnaryOp};
+use super::diagnostic::{Diagnostic, Span};
+use super::parser::fold;
+use std::collections::HashMap;
+
+/// Infers the types of a program and makes its conversions explicit
+///
+/// Unknown names are left to the codegen, which reports them
+struct Checker<'f> {
+    file: &'f str,
+    globals: HashMap<String, Type>,
+    /// Locals of the function being checked, then the top-level ones
+    scopes: Vec<HashMap<String, Option<Type>>>,
+    /// Signatures of the functions of the program, `true` for parameters by reference
+    functions: HashMap<String, (Vec<(Type, bool)>, Type)>,
+    /// Return types of the functions being checked
+    returns: Vec<Type>,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'f> Checker<'f> {
+    fn mismatch(&mut self, expected: &str, found: &Type, span: Span) {
+        let message = format!("mismatched types: expected {}, found {}", expected, found);
+        self.errors.push(Diagnostic::new(self.file, span, &message));
+    }
+
+    /// Type of a variable, functions also see the top-level ones
+    fn lookup(&self, name: &str) -> Option<Type> {
+        match (
+            self.scopes.last().unwrap().get(name),
+            self.scopes[0].get(name),
+        ) {
+            (Some(ty), _) | (None, Some(ty)) => ty.clone(),
+            (None, None) => self.globals.get(name).cloned(),
+        }
+    }
+
+    fn declare(&mut self, ident: &Node, ty: Option<Type>, global: bool) {
+        if let NodeKind::IdentExpr(name) = &ident.kind {
+            match (global, ty) {
+                (true, Some(ty)) => {
+                    self.globals.insert(name.clone(), ty);
+                }
+                (true, None) => (),
+                (false, ty) => {
+                    self.scopes.last_mut().unwrap().insert(name.clone(), ty);
+                }
+            }
+        }
+    }
+
+    /// Converts `node` of type `found` to `expected`, scalars only
+    fn cast(&mut self, node: Node, found: &Option<Type>, expected: &Type) -> Node {
+        match found {
+            Some(found) if found == expected => node,
+            Some(found) if found.is_scalar() && expected.is_scalar() => {
+                let span = node.span;
+                Node::new(
+                    NodeKind::CastExpr {
+                        ty: expected.clone(),
+                        expr: Box::new(node),
+                    },
+                    span,
+                )
+            }
+            Some(found) => {
+                self.mismatch(&expected.to_string(), found, node.span);
+                node
+            }
+            None => node,
+        }
+    }
+
+    /// Like `cast`, but blocks convert their last value
+    fn cast_value(&mut self, node: Node, found: &Option<Type>, expected: &Type) -> Node {
+        match node.kind {
+            NodeKind::BlockExpr { mut nodes, tail } if !nodes.is_empty() => {
+                let last = nodes.pop().unwrap();
+                nodes.push(self.cast_value(last, found, expected));
+                Node::new(NodeKind::BlockExpr { nodes, tail }, node.span)
+            }
+            kind => self.cast(Node::new(kind, node.span), found, expected),
+        }
+    }
+
+    /// Returns the value of `node`, the last one of blocks
+    fn implicit_return(&mut self, node: Node, found: &Option<Type>, ret: &Type) -> Node {
+        match node.kind {
+            NodeKind::BlockExpr { mut nodes, tail } if !nodes.is_empty() => {
+                let last = nodes.pop().unwrap();
+                nodes.push(self.implicit_return(last, found, ret));
+                Node::new(NodeKind::BlockExpr { nodes, tail }, node.span)
+            }
+            kind => {
+                let span = node.span;
+                let value = self.cast(Node::new(kind, span), found, ret);
+                let ret = Box::new(value);
+                Node::new(NodeKind::ReturnExpr { ret }, span)
+            }
+        }
+    }
+
+    /// Checks an operand which must be a number
+    fn number(&mut self, node: Node) -> (Node, Option<Type>) {
+        let (node, ty) = self.check(node);
+        self.numeric(node, ty)
+    }
+
+    /// Operand which must be a number, booleans count as integers
+    fn numeric(&mut self, node: Node, ty: Option<Type>) -> (Node, Option<Type>) {
+        match (node, ty) {
+            (node, Some(Type::Bool)) => {
+                let node = self.cast(node, &Some(Type::Bool), &Type::Int);
+                (node, Some(Type::Int))
+            }
+            (node, Some(Type::Int)) => (node, Some(Type::Int)),
+            (node, Some(Type::Float)) => (node, Some(Type::Float)),
+            (node, Some(ty)) => {
+                self.mismatch("number", &ty, node.span);
+                (node, None)
+            }
+            (node, None) => (node, None),
+        }
+    }
+
+    /// Checks a node converted to `ty`
+    fn expect(&mut self, node: Node, ty: &Type) -> Node {
+        let (node, found) = self.check(node);
+        self.cast(node, &found, ty)
+    }
+
+    /// Numeric operands, integers are converted when mixed with floats
+    fn operands(
+        &mut self,
+        (lhs, lhs_ty): (Node, Option<Type>),
+        (rhs, rhs_ty): (Node, Option<Type>),
+    ) -> (Node, Node, Option<Type>) {
+        let (lhs, lhs_ty) = self.numeric(lhs, lhs_ty);
+        let (rhs, rhs_ty) = self.numeric(rhs, rhs_ty);
+        match (&lhs_ty, &rhs_ty) {
+            (Some(Type::Int), Some(Type::Int)) => (lhs, rhs, Some(Type::Int)),
+            (Some(_), Some(_)) => {
+                let lhs = self.cast(lhs, &lhs_ty, &Type::Float);
+                let rhs = self.cast(rhs, &rhs_ty, &Type::Float);
+                (lhs, rhs, Some(Type::Float))
+            }
+            _ => (lhs, rhs, None),
+        }
+    }
+
+    /// Records the signatures of the functions defined in `node`, which may be
+    /// called before their definition
+    fn declare_functions(&mut self, node: &Node) {
+        if let NodeKind::FuncExpr {
+            ident,
+            args,
+            ret,
+            body,
+        } = &node.kind
+        {
+            if let NodeKind::IdentExpr(name) = &ident.kind {
+                let params = args
+                    .iter()
+                    .map(|arg| (arg.type_in(body), arg.by_ref))
+                    .collect();
+                let ret = ret.clone().unwrap_or(Type::Float);
+                self.functions.insert(name.clone(), (params, ret));
+            }
+        }
+        for child in node.children() {
+            self.declare_functions(child);
+        }
+    }
+
+    /// Type of a construct whose branches meet, which are converted to it
+    ///
+    /// Branches without a value give a float (NAN), scalars end up as floats.
+    /// Branches which jump away never reach the merge.
+    fn merge(
+        &mut self,
+        branches: Vec<(Node, Option<Type>)>,
+        missing: bool,
+    ) -> (Vec<Node>, Option<Type>) {
+        let mut types: Vec<Type> = branches
+            .iter()
+            .filter(|(node, _)| !node.diverges())
+            .map(|(_, ty)| ty.clone().unwrap_or(Type::Float))
+            .collect();
+        if missing {
+            types.push(Type::Float);
+        }
+
+        let ty = match types.first() {
+            Some(first) if types.iter().all(|ty| ty == first) => Some(first.clone()),
+            Some(_) if types.iter().all(Type::is_scalar) => Some(Type::Float),
+            _ => None,
+        };
+
+        let nodes = branches
+            .into_iter()
+            .map(|(node, found)| match &ty {
+                Some(ty) => self.cast_value(node, &found, ty),
+                None => node,
+            })
+            .collect();
+        (nodes, ty)
+    }
+
+    fn check(&mut self, node: Node) -> (Node, Option<Type>) {
+        let span = node.span;
+        let (kind, ty) = match node.kind {
+            NodeKind::IntExpr(nb) => (NodeKind::IntExpr(nb), Some(Type::Int)),
+            NodeKind::NumberExpr(nb) => (NodeKind::NumberExpr(nb), Some(Type::Float)),
+            NodeKind::BoolExpr(b) => (NodeKind::BoolExpr(b), Some(Type::Bool)),
+            NodeKind::CastExpr { ty, expr } => {
+                let expr = Box::new(self.check(*expr).0);
+                (
+                    NodeKind::CastExpr {
+                        ty: ty.clone(),
+                        expr,
+                    },
+                    Some(ty),
+                )
+            }
+            NodeKind::IdentExpr(name) => {
+                // Arrays used without an index are reported by the codegen
+                let ty = match self.lookup(&name) {
+                    Some(Type::Array(_)) => None,
+                    ty => ty,
+                };
+                (NodeKind::IdentExpr(name), ty)
+            }
+            NodeKind::IndexExpr { ident, index } => {
+                let ty = match &ident.kind {
+                    NodeKind::IdentExpr(name) => match self.lookup(name) {
+                        Some(Type::Array(elem)) => Some(*elem),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let (index, found) = self.number(*index);
+                let index = Box::new(self.cast(index, &found, &Type::Int));
+                (NodeKind::IndexExpr { ident, index }, ty)
+            }
+
+            NodeKind::UnaryExpr { op, child } => {
+                let (child, ty) = match op {
+                    UnaryOp::Address => {
+                        let (child, ty) = self.check(*child);
+                        (child, ty.map(|ty| Type::Pointer(Box::new(ty))))
+                    }
+                    UnaryOp::Deref => match self.check(*child) {
+                        (child, Some(Type::Pointer(ty))) => (child, Some(*ty)),
+                        (child, Some(ty)) => {
+                            self.mismatch("pointer", &ty, child.span);
+                            (child, None)
+                        }
+                        (child, None) => (child, None),
+                    },
+                    UnaryOp::Sub => self.number(*child),
+                    UnaryOp::Not => (self.expect(*child, &Type::Bool), Some(Type::Bool)),
+                    UnaryOp::Factorial => {
+                        let (child, found) = self.number(*child);
+                        (self.cast(child, &found, &Type::Float), Some(Type::Float))
+                    }
+                };
+                let child = Box::new(child);
+                (NodeKind::UnaryExpr { op, child }, ty)
+            }
+            NodeKind::BinaryExpr { op, lhs, rhs } => {
+                let (lhs, rhs, ty) = match op {
+                    BinaryOp::And | BinaryOp::Or => {
+                        let lhs = self.expect(*lhs, &Type::Bool);
+                        let rhs = self.expect(*rhs, &Type::Bool);
+                        (lhs, rhs, Some(Type::Bool))
+                    }
+                    BinaryOp::Pow => {
+                        let (lhs, lhs_ty) = self.number(*lhs);
+                        let (rhs, rhs_ty) = self.number(*rhs);
+                        match (&lhs_ty, int_exponent(&rhs)) {
+                            (Some(Type::Int), Some(_)) => (lhs, rhs, Some(Type::Int)),
+                            _ => {
+                                let lhs = self.cast(lhs, &lhs_ty, &Type::Float);
+                                let rhs = self.cast(rhs, &rhs_ty, &Type::Float);
+                                (lhs, rhs, Some(Type::Float))
+                            }
+                        }
+                    }
+                    BinaryOp::Add
+                    | BinaryOp::Sub
+                    | BinaryOp::Mul
+                    | BinaryOp::Div
+                    | BinaryOp::Modulo => {
+                        let (lhs, rhs) = (self.check(*lhs), self.check(*rhs));
+                        self.operands(lhs, rhs)
+                    }
+                    // Comparisons, booleans may only be compared for equality
+                    _ => {
+                        let (lhs, rhs) = (self.check(*lhs), self.check(*rhs));
+                        let equality = op == BinaryOp::Eq || op == BinaryOp::Ne;
+                        match (&lhs.1, &rhs.1) {
+                            (Some(Type::Bool), Some(Type::Bool)) if equality => {
+                                (lhs.0, rhs.0, Some(Type::Bool))
+                            }
+                            _ => {
+                                let (lhs, rhs, _) = self.operands(lhs, rhs);
+                                (lhs, rhs, Some(Type::Bool))
+                            }
+                        }
+                    }
+                };
+                let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+                (NodeKind::BinaryExpr { op, lhs, rhs }, ty)
+            }
+
+            NodeKind::InitExpr { ident, ty, expr } => {
+                let (expr, found) = self.initializer(*expr, &ty);
+                self.declare(&ident, found, false);
+                (NodeKind::InitExpr { ident, ty, expr }, None)
+            }
+            NodeKind::GlobalInitExpr { ident, ty, expr } => {
+                let (expr, found) = self.initializer(*expr, &ty);
+                self.declare(&ident, found, true);
+                (NodeKind::GlobalInitExpr { ident, ty, expr }, None)
+            }
+            // Elements are floats unless annotated
+            NodeKind::ArrayInitExpr { ident, size, ty } => {
+                let elem = ty.clone().unwrap_or(Type::Float);
+                self.declare(&ident, Some(Type::Array(Box::new(elem))), false);
+                (NodeKind::ArrayInitExpr { ident, size, ty }, None)
+            }
+            NodeKind::GlobalArrayInitExpr { ident, size, ty } => {
+                let elem = ty.clone().unwrap_or(Type::Float);
+                self.declare(&ident, Some(Type::Array(Box::new(elem))), true);
+                (NodeKind::GlobalArrayInitExpr { ident, size, ty }, None)
+            }
+            NodeKind::AssignExpr { ident, expr } => {
+                let (ident, ty) = self.check(*ident);
+                let expr = match &ty {
+                    Some(ty) => self.expect(*expr, ty),
+                    None => self.check(*expr).0,
+                };
+                let (ident, expr) = (Box::new(ident), Box::new(expr));
+                (NodeKind::AssignExpr { ident, expr }, ty)
+            }
+
+            NodeKind::CondExpr { cond, cons, alter } => {
+                let cond = Box::new(self.expect(*cond, &Type::Bool));
+                let mut branches = vec![self.check(*cons)];
+                let missing = alter.is_none();
+                if let Some(alter) = alter {
+                    branches.push(self.check(*alter));
+                }
+
+                let (mut nodes, ty) = self.merge(branches, missing);
+                let alter = match missing {
+                    true => None,
+                    false => Some(Box::new(nodes.pop().unwrap())),
+                };
+                let cons = Box::new(nodes.pop().unwrap());
+                (NodeKind::CondExpr { cond, cons, alter }, ty)
+            }
+            NodeKind::CaseExpr {
+                expr,
+                arms,
+                default,
+            } => {
+                let (expr, found) = self.number(*expr);
+                let expr = Box::new(self.cast(expr, &found, &Type::Int));
+
+                let labels: Vec<i64> = arms.iter().map(|(label, _)| *label).collect();
+                let mut branches: Vec<(Node, Option<Type>)> =
+                    arms.into_iter().map(|(_, body)| self.check(body)).collect();
+                let missing = default.is_none();
+                if let Some(default) = default {
+                    branches.push(self.check(*default));
+                }
+
+                let (mut nodes, ty) = self.merge(branches, missing);
+                let default = match missing {
+                    true => None,
+                    false => Some(Box::new(nodes.pop().unwrap())),
+                };
+                let arms = labels.into_iter().zip(nodes).collect();
+                (
+                    NodeKind::CaseExpr {
+                        expr,
+                        arms,
+                        default,
+                    },
+                    ty,
+                )
+            }
+            NodeKind::WhileExpr { cond, body } => {
+                let cond = Box::new(self.expect(*cond, &Type::Bool));
+                let body = Box::new(self.check(*body).0);
+                (NodeKind::WhileExpr { cond, body }, None)
+            }
+            // The loop variable is a float if any of the bounds or the step is
+            NodeKind::ForExpr {
+                ident,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                if let Some(step) = &step {
+                    let zero = match fold(step) {
+                        Some(Constant::Int(value)) => value == 0,
+                        Some(Constant::Float(value)) => value == 0.0,
+                        _ => false,
+                    };
+                    if zero {
+                        let message = "the step of a range cannot be zero";
+                        self.errors
+                            .push(Diagnostic::new(self.file, step.span, message));
+                    }
+                }
+                let mut bounds = vec![self.number(*start), self.number(*end)];
+                bounds.extend(step.map(|step| self.number(*step)));
+                let ty = match bounds.iter().all(|(_, ty)| ty == &Some(Type::Int)) {
+                    true => Type::Int,
+                    false => Type::Float,
+                };
+                let mut bounds = bounds
+                    .into_iter()
+                    .map(|(node, found)| Box::new(self.cast(node, &found, &ty)));
+                let (start, end) = (bounds.next().unwrap(), bounds.next().unwrap());
+                let step = bounds.next();
+
+                self.declare(&ident, Some(ty), false);
+                let body = Box::new(self.check(*body).0);
+                (
+                    NodeKind::ForExpr {
+                        ident,
+                        start,
+                        end,
+                        step,
+                        body,
+                    },
+                    None,
+                )
+            }
+            // Blocks ending with an expression statement have no value
+            NodeKind::BlockExpr { nodes, tail } => {
+                let value = Node::block_value(&nodes, tail).is_some();
+                let mut ty = None;
+                let nodes = nodes
+                    .into_iter()
+                    .map(|node| {
+                        let (node, node_ty) = self.check(node);
+                        ty = node_ty;
+                        node
+                    })
+                    .collect();
+                let ty = match value {
+                    true => ty,
+                    false => None,
+                };
+                (NodeKind::BlockExpr { nodes, tail }, ty)
+            }
+
+            NodeKind::FuncExpr {
+                ident,
+                args,
+                ret,
+                body,
+            } => {
+                let scope = args
+                    .iter()
+                    .map(|arg| (arg.name.clone(), Some(arg.type_in(&body))))
+                    .collect();
+                let ret_ty = ret.clone().unwrap_or(Type::Float);
+                self.scopes.push(scope);
+                self.returns.push(ret_ty.clone());
+                let (body, found) = self.check(*body);
+                self.returns.pop();
+                self.scopes.pop();
+
+                // The final value is returned, floats may miss it like branches (NAN)
+                let body = match (body.always_returns(), &found) {
+                    (true, _) => body,
+                    (false, Some(_)) if body.has_value() || ret_ty == Type::Float => {
+                        self.implicit_return(body, &found, &ret_ty)
+                    }
+                    (false, _) if ret_ty == Type::Float => body,
+                    (false, _) => {
+                        let message = "not all paths return a value";
+                        self.errors
+                            .push(Diagnostic::new(self.file, ident.span, message));
+                        body
+                    }
+                };
+                let body = Box::new(body);
+
+                (
+                    NodeKind::FuncExpr {
+                        ident,
+                        args,
+                        ret,
+                        body,
+                    },
+                    None,
+                )
+            }
+            NodeKind::ReturnExpr { ret } => {
+                let ty = self.returns.last().unwrap().clone();
+                let ret = Box::new(self.expect(*ret, &ty));
+                (NodeKind::ReturnExpr { ret }, None)
+            }
+            NodeKind::CallExpr { ident, args } if ident.is_builtin_len() => {
+                (NodeKind::CallExpr { ident, args }, Some(Type::Int))
+            }
+            NodeKind::CallExpr { ident, args } => {
+                let signature = match &ident.kind {
+                    NodeKind::IdentExpr(name) => self.functions.get(name).cloned(),
+                    _ => None,
+                };
+                match signature {
+                    // Arity errors are reported by the codegen
+                    Some((params, ret)) if params.len() == args.len() => {
+                        let args = args
+                            .into_iter()
+                            .zip(params)
+                            .map(|(arg, param)| self.argument(arg, param))
+                            .collect();
+                        (NodeKind::CallExpr { ident, args }, Some(ret))
+                    }
+                    _ => {
+                        let args = args.into_iter().map(|arg| self.check(arg).0).collect();
+                        (NodeKind::CallExpr { ident, args }, None)
+                    }
+                }
+            }
+
+            kind @ NodeKind::ConstExpr { .. }
+            | kind @ NodeKind::ArrayExpr(_)
+            | kind @ NodeKind::BreakExpr
+            | kind @ NodeKind::ContinueExpr => (kind, None),
+        };
+        (Node::new(kind, span), ty)
+    }
+
+    /// Initializer of a declaration annotated with `ty`, array literals hold floats by default
+    fn initializer(&mut self, expr: Node, ty: &Option<Type>) -> (Box<Node>, Option<Type>) {
+        match expr.kind {
+            NodeKind::ArrayExpr(values) => {
+                let elem = ty.clone().unwrap_or(Type::Float);
+                let values = values
+                    .into_iter()
+                    .map(|value| self.expect(value, &elem))
+                    .collect();
+                (
+                    Box::new(Node::new(NodeKind::ArrayExpr(values), expr.span)),
+                    Some(Type::Array(Box::new(elem))),
+                )
+            }
+            kind => {
+                let (expr, found) = self.check(Node::new(kind, expr.span));
+                match ty {
+                    Some(ty) => (Box::new(self.cast(expr, &found, ty)), Some(ty.clone())),
+                    None => (Box::new(expr), found),
+                }
+            }
+        }
+    }
+
+    /// Argument of a call, references must designate a variable of the right type
+    fn argument(&mut self, arg: Node, (ty, by_ref): (Type, bool)) -> Node {
+        let place = matches!(
+            arg.kind,
+            NodeKind::IdentExpr(_)
+                | NodeKind::IndexExpr { .. }
+                | NodeKind::UnaryExpr {
+                    op: UnaryOp::Deref,
+                    ..
+                }
+        );
+        match (by_ref, &ty) {
+            // Arrays are passed by name, their elements must match
+            (true, Type::Array(_)) => {
+                let found = match &arg.kind {
+                    NodeKind::IdentExpr(name) => self.lookup(name),
+                    _ => None,
+                };
+                match found {
+                    Some(found @ Type::Array(_)) if found != ty => {
+                        self.mismatch(&ty.to_string(), &found, arg.span)
+                    }
+                    _ => (),
+                }
+                arg
+            }
+            (true, _) if !place => self.check(arg).0,
+            (true, _) => {
+                let (arg, found) = self.check(arg);
+                match found {
+                    Some(found) if found != ty => {
+                        let expected = Type::Pointer(Box::new(ty));
+                        self.mismatch(
+                            &expected.to_string(),
+                            &Type::Pointer(Box::new(found)),
+                            arg.span,
+                        );
+                        arg
+                    }
+                    _ => arg,
+                }
+            }
+            (false, _) => self.expect(arg, &ty),
+        }
+    }
+}
+
+/// Exponent of a `^` computed on integers, integers stay integers under a
+/// constant natural exponent
+pub fn int_exponent(exp: &Node) -> Option<u32> {
+    match fold(exp) {
+        Some(Constant::Int(exp)) if (0..=i64::from(u32::MAX)).contains(&exp) => Some(exp as u32),
+        _ => None,
+    }
+}
+
+/// Type checks a parsed program, returning it with explicit conversions
+pub fn check(file: &str, nodes: Vec<Node>) -> Result<Vec<Node>, Vec<Diagnostic>> {
+    let mut checker = Checker {
+        file,
+        globals: HashMap::new(),
+        scopes: vec![HashMap::new()],
+        functions: HashMap::new(),
+        returns: vec![Type::Float],
+        errors: vec![],
+    };
+    for node in &nodes {
+        checker.declare_functions(node);
+    }
+    let nodes = nodes
+        .into_iter()
+        .map(|node| checker.check(node).0)
+        .collect();
+
+    if checker.errors.is_empty() {
+        Ok(nodes)
+    } else {
+        Err(checker.errors)
+    }
+}
+
+#[cfg(test)]
+mod typing {
+    use super::*;
+    use crate::parser::parse;
+
+    fn check_single(string: &str) -> Node {
+        check("test", parse("test", string).unwrap())
+            .unwrap()
+            .pop()
+            .unwrap()
+    }
+
+    fn node(kind: NodeKind) -> Node {
+        Node::from(kind)
+    }
+
+    fn cast(ty: Type, kind: NodeKind) -> Box<Node> {
+        Box::new(node(NodeKind::CastExpr {
+            ty,
+            expr: Box::new(node(kind)),
+        }))
+    }
+
+    #[test]
+    fn mixed_operands() {
+        assert_eq!(
+            check_single("1 + 2.5"),
+            node(NodeKind::BinaryExpr {
+                op: BinaryOp::Add,
+                lhs: cast(Type::Float, NodeKind::IntExpr(1)),
+                rhs: Box::new(node(NodeKind::NumberExpr(2.5)))
+            })
+        )
+    }
+
+    #[test]
+    fn int_operands() {
+        assert_eq!(
+            check_single("7 % 2"),
+            node(NodeKind::BinaryExpr {
+                op: BinaryOp::Modulo,
+                lhs: Box::new(node(NodeKind::IntExpr(7))),
+                rhs: Box::new(node(NodeKind::IntExpr(2)))
+            })
+        )
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(
+            check_single("while 1 {}"),
+            node(NodeKind::WhileExpr {
+                cond: cast(Type::Bool, NodeKind::IntExpr(1)),
+                body: Box::new(node(NodeKind::BlockExpr {
+                    nodes: vec![],
+                    tail: false,
+                }))
+            })
+        );
+        assert_eq!(
+            check_single("!2"),
+            node(NodeKind::UnaryExpr {
+                op: UnaryOp::Not,
+                child: cast(Type::Bool, NodeKind::IntExpr(2))
+            })
+        );
+    }
+
+    #[test]
+    fn bool_arithmetic() {
+        assert_eq!(
+            check_single("true + 1"),
+            node(NodeKind::BinaryExpr {
+                op: BinaryOp::Add,
+                lhs: cast(Type::Int, NodeKind::BoolExpr(true)),
+                rhs: Box::new(node(NodeKind::IntExpr(1)))
+            })
+        )
+    }
+
+    #[test]
+    fn assignment() {
+        assert_eq!(
+            check_single("let b = true; b = 2;"),
+            node(NodeKind::AssignExpr {
+                ident: Box::new(node(NodeKind::IdentExpr(String::from("b")))),
+                expr: cast(Type::Bool, NodeKind::IntExpr(2))
+            })
+        )
+    }
+
+    #[test]
+    fn annotations() {
+        assert_eq!(
+            check_single("let x: float = 0;"),
+            node(NodeKind::InitExpr {
+                ident: Box::new(node(NodeKind::IdentExpr(String::from("x")))),
+                ty: Some(Type::Float),
+                expr: cast(Type::Float, NodeKind::IntExpr(0))
+            })
+        );
+        assert_eq!(
+            check_single("fn half(a: int) -> int { return a / 2; } half(7) + 1"),
+            node(NodeKind::BinaryExpr {
+                op: BinaryOp::Add,
+                lhs: Box::new(node(NodeKind::CallExpr {
+                    ident: Box::new(node(NodeKind::IdentExpr(String::from("half")))),
+                    args: vec![node(NodeKind::IntExpr(7))]
+                })),
+                rhs: Box::new(node(NodeKind::IntExpr(1)))
+            })
+        )
+    }
+
+    #[test]
+    fn forward_call() {
+        let nodes = check(
+            "test",
+            parse(
+                "test",
+                "let a = half(7.5); fn half(a: int) -> int { return a / 2; }",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            nodes[0],
+            node(NodeKind::InitExpr {
+                ident: Box::new(node(NodeKind::IdentExpr(String::from("a")))),
+                ty: None,
+                expr: Box::new(node(NodeKind::CallExpr {
+                    ident: Box::new(node(NodeKind::IdentExpr(String::from("half")))),
+                    args: vec![*cast(Type::Int, NodeKind::NumberExpr(7.5))]
+                }))
+            })
+        );
+    }
+
+    #[test]
+    fn implicit_return() {
+        match check_single("fn f(a: int) -> float { a * 2 }").kind {
+            NodeKind::FuncExpr { body, .. } => assert_eq!(
+                *body,
+                node(NodeKind::BlockExpr {
+                    nodes: vec![node(NodeKind::ReturnExpr {
+                        ret: cast(
+                            Type::Float,
+                            NodeKind::BinaryExpr {
+                                op: BinaryOp::Mul,
+                                lhs: Box::new(node(NodeKind::IdentExpr(String::from("a")))),
+                                rhs: Box::new(node(NodeKind::IntExpr(2)))
+                            }
+                        )
+                    })],
+                    tail: true,
+                })
+            ),
+            kind => panic!("expected a function, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn missing_return() {
+        let errors = check(
+            "test",
+            parse(
+                "test",
+                "fn f(a: int) -> int {\n  if a then { return 1; }\n}",
+            )
+            .unwrap(),
+        )
+        .unwrap_err();
+        assert_eq!(errors[0].message, "not all paths return a value");
+        assert_eq!((errors[0].span.line, errors[0].span.col), (1, 4));
+
+        // Expression statements are not the value of the function
+        for source in &[
+            "fn f(a: int) -> int { if a then { return 1; } x = 2; }",
+            "fn g() -> int { return 1; } fn f() -> int { g(); }",
+        ] {
+            let errors = check("test", parse("test", source).unwrap()).unwrap_err();
+            assert_eq!(errors[0].message, "not all paths return a value");
+        }
+    }
+
+    #[test]
+    fn returning_branch() {
+        assert_eq!(
+            check_single("if true then { return 1; } else { 5 }"),
+            node(NodeKind::CondExpr {
+                cond: Box::new(node(NodeKind::BoolExpr(true))),
+                cons: Box::new(node(NodeKind::BlockExpr {
+                    nodes: vec![node(NodeKind::ReturnExpr {
+                        ret: cast(Type::Float, NodeKind::IntExpr(1))
+                    })],
+                    tail: false,
+                })),
+                alter: Some(Box::new(node(NodeKind::BlockExpr {
+                    nodes: vec![node(NodeKind::IntExpr(5))],
+                    tail: true,
+                })))
+            })
+        )
+    }
+
+    #[test]
+    fn for_range() {
+        let ident = || Box::new(node(NodeKind::IdentExpr(String::from("i"))));
+        assert_eq!(
+            check_single("for i in 0..2.5 { i }"),
+            node(NodeKind::ForExpr {
+                ident: ident(),
+                start: cast(Type::Float, NodeKind::IntExpr(0)),
+                end: Box::new(node(NodeKind::NumberExpr(2.5))),
+                step: None,
+                body: Box::new(node(NodeKind::BlockExpr {
+                    nodes: vec![*ident()],
+                    tail: true,
+                }))
+            })
+        );
+        let errors = check("test", parse("test", "for i in 0..3 step 0 {}").unwrap()).unwrap_err();
+        assert_eq!(errors[0].message, "the step of a range cannot be zero");
+        assert_eq!((errors[0].span.line, errors[0].span.col), (1, 20));
+    }
+
+    #[test]
+    fn mismatch() {
+        let errors = check(
+            "test",
+            parse("test", "let a = 1;\nlet p = &a; *p + *a").unwrap(),
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "mismatched types: expected pointer, found int"
+        );
+        assert_eq!((errors[0].span.line, errors[0].span.col), (2, 19));
+    }
+}